@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    http::{header::AUTHORIZATION, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use keycloak::{KeycloakClient, TokenClaim};
+
+use crate::{errors::AxError, state::AppState};
+
+/// Middleware that validates the bearer token with the [`KeycloakClient`] found in the
+/// request extensions or the [`AppState`] and stores its [`TokenClaim`] for the
+/// [`Claims`] extractor.
+///
+/// ```ignore
+/// let routes = Router::new()
+///     .route("/me", get(me))
+///     .route_layer(middleware::from_fn(require_auth))
+///     .layer(Extension(keycloak));
+/// ```
+pub async fn require_auth<B>(mut req: Request<B>, next: Next<B>) -> Result<Response, AxError> {
+    let keycloak = req
+        .extensions()
+        .get::<KeycloakClient>()
+        .cloned()
+        .or_else(|| {
+            req.extensions()
+                .get::<AppState>()
+                .and_then(AppState::get::<KeycloakClient>)
+        })
+        .ok_or_else(|| {
+            AxError::InternalServerErrorWithContext("keycloak client is not configured".to_owned())
+        })?;
+    let token = bearer_token(req.headers()).ok_or(AxError::Unauthorized)?;
+    let claims = keycloak
+        .decode::<TokenClaim>(token.to_owned())
+        .map_err(|_| AxError::Unauthorized)?;
+    req.extensions_mut().insert(claims);
+    Ok(next.run(req).await)
+}
+
+/// Middleware that rejects callers of [`require_auth`] lacking any of the realm `roles`.
+///
+/// ```ignore
+/// let roles: Arc<[String]> = Arc::new(["admin".to_owned()]);
+/// let routes = Router::new()
+///     .route("/users", get(users))
+///     .route_layer(middleware::from_fn(move |req, next| require_roles(req, next, roles.clone())))
+///     .route_layer(middleware::from_fn(require_auth));
+/// ```
+pub async fn require_roles<B>(
+    req: Request<B>,
+    next: Next<B>,
+    roles: Arc<[String]>,
+) -> Result<Response, AxError> {
+    let claims = req
+        .extensions()
+        .get::<TokenClaim>()
+        .ok_or(AxError::Unauthorized)?;
+    if !roles.iter().all(|role| claims.has_role(role)) {
+        return Err(AxError::Forbidden);
+    }
+    Ok(next.run(req).await)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// Claims of the caller authenticated by [`require_auth`].
+#[derive(Debug, Clone)]
+pub struct Claims(pub TokenClaim);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Claims {
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        req.extensions()
+            .get::<TokenClaim>()
+            .cloned()
+            .map(Claims)
+            .ok_or(AxError::Unauthorized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        middleware::from_fn,
+        routing::get,
+        Extension, Router,
+    };
+    use keycloak::{testing::TokenMinter, KeycloakClient};
+
+    use super::{require_auth, Claims};
+    use crate::test_util::send_text;
+
+    async fn me(Claims(claims): Claims) -> String {
+        claims.sub
+    }
+
+    fn app(minter: &TokenMinter) -> Router {
+        let keycloak: KeycloakClient = Arc::new(minter.keycloak());
+        Router::new()
+            .route("/me", get(me))
+            .route_layer(from_fn(require_auth))
+            .layer(Extension(keycloak))
+    }
+
+    async fn call(app: Router, authorization: Option<String>) -> (StatusCode, String) {
+        let mut req = Request::builder().uri("/me");
+        if let Some(authorization) = authorization {
+            req = req.header(AUTHORIZATION, authorization);
+        }
+        send_text(app, req.body(Body::empty()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_require_auth() {
+        let minter = TokenMinter::new();
+
+        let token = minter.token().subject("alice").roles(&["admin"]).sign();
+        let (status, body) = call(app(&minter), Some(format!("Bearer {}", token))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "alice");
+
+        let (status, _) = call(app(&minter), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let expired = minter.token().expired().sign();
+        let (status, _) = call(app(&minter), Some(format!("Bearer {}", expired))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = call(app(&minter), Some(format!("Basic {}", token))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,717 @@
+use std::{
+    env, fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use axum::http::HeaderValue;
+use axum_server::HttpConfig;
+use serde::Deserialize;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::Level;
+
+use crate::{
+    errors::AxError,
+    openapi::{DocsAsset, DocsAssets, DocsUi},
+    problem::ErrorFormat,
+    tls::TlsConfig,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    #[default]
+    V4,
+    V6,
+}
+
+impl FromStr for IpVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "v4" | "ipv4" | "4" => Ok(IpVersion::V4),
+            "v6" | "ipv6" | "6" => Ok(IpVersion::V6),
+            _ => Err(format!("unknown ip version {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for TraceLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(TraceLevel::Error),
+            "warn" => Ok(TraceLevel::Warn),
+            "info" => Ok(TraceLevel::Info),
+            "debug" => Ok(TraceLevel::Debug),
+            "trace" => Ok(TraceLevel::Trace),
+            _ => Err(format!("unknown trace level {}", s)),
+        }
+    }
+}
+
+impl From<TraceLevel> for Level {
+    fn from(value: TraceLevel) -> Self {
+        match value {
+            TraceLevel::Error => Level::ERROR,
+            TraceLevel::Warn => Level::WARN,
+            TraceLevel::Info => Level::INFO,
+            TraceLevel::Debug => Level::DEBUG,
+            TraceLevel::Trace => Level::TRACE,
+        }
+    }
+}
+
+/// Connection level HTTP settings, hyper's defaults unless set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpSettings {
+    /// Accept HTTP/2, negotiated over ALPN with TLS and with prior knowledge (h2c)
+    /// on plain connections.
+    pub http2: bool,
+    /// Refuse HTTP/1 connections, for gRPC only services.
+    pub http2_only: bool,
+    pub http2_max_concurrent_streams: Option<u32>,
+    /// Interval of HTTP/2 keep-alive pings, pings are disabled when empty.
+    pub http2_keep_alive_interval_secs: Option<u64>,
+    /// How long to wait for a ping acknowledgement before closing the connection.
+    pub http2_keep_alive_timeout_secs: u64,
+    pub http1_keep_alive: bool,
+    /// How long a client may take to send the request headers of an HTTP/1 request.
+    pub header_read_timeout_secs: Option<u64>,
+    /// Maximum size of the HTTP/1 read buffer in bytes, which bounds the request
+    /// head. At least 8192.
+    pub max_header_size: Option<usize>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            http2: true,
+            http2_only: false,
+            http2_max_concurrent_streams: None,
+            http2_keep_alive_interval_secs: None,
+            http2_keep_alive_timeout_secs: 20,
+            http1_keep_alive: true,
+            header_read_timeout_secs: None,
+            max_header_size: None,
+        }
+    }
+}
+
+impl HttpSettings {
+    pub fn validate(&self) -> Result<(), AxError> {
+        if self.http2_only && !self.http2 {
+            return Err(AxError::ApplicationStartup(
+                "http2_only requires http2 to be enabled".to_owned(),
+            ));
+        }
+        if self.http2_max_concurrent_streams == Some(0) {
+            return Err(AxError::ApplicationStartup(
+                "http2_max_concurrent_streams must be greater than 0".to_owned(),
+            ));
+        }
+        if self.http2_keep_alive_interval_secs == Some(0) || self.http2_keep_alive_timeout_secs == 0
+        {
+            return Err(AxError::ApplicationStartup(
+                "http2 keep-alive interval and timeout must be greater than 0".to_owned(),
+            ));
+        }
+        if self.header_read_timeout_secs == Some(0) {
+            return Err(AxError::ApplicationStartup(
+                "header_read_timeout_secs must be greater than 0".to_owned(),
+            ));
+        }
+        if matches!(self.max_header_size, Some(size) if size < 8192) {
+            return Err(AxError::ApplicationStartup(
+                "max_header_size must be at least 8192".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn http_config(&self) -> HttpConfig {
+        let mut config = HttpConfig::new();
+        // hyper keeps a single protocol mode, `http2_only(false)` would undo `http1_only`
+        if !self.http2 {
+            config.http1_only(true);
+        } else if self.http2_only {
+            config.http2_only(true);
+        }
+        config
+            .http1_keep_alive(self.http1_keep_alive)
+            .http2_max_concurrent_streams(self.http2_max_concurrent_streams)
+            .http2_keep_alive_interval(self.http2_keep_alive_interval_secs.map(Duration::from_secs))
+            .http2_keep_alive_timeout(Duration::from_secs(self.http2_keep_alive_timeout_secs));
+        if let Some(timeout) = self.header_read_timeout_secs {
+            config.http1_header_read_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(size) = self.max_header_size {
+            config.max_buf_size(size);
+        }
+        config.build()
+    }
+
+    /// Protocols offered over ALPN, in order of preference.
+    pub(crate) fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        match (self.http2, self.http2_only) {
+            (true, true) => vec![b"h2".to_vec()],
+            (true, false) => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            (false, _) => vec![b"http/1.1".to_vec()],
+        }
+    }
+}
+
+/// Settings of the server started by [`crate::ApiGateway::serve`].
+///
+/// Every field has a default, so env variables and config files only need to list
+/// what they change. Call [`GatewayConfig::validate`] (done by `serve`) before use.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GatewayConfig {
+    /// Address to bind; the unspecified address of `ip_version` when empty.
+    pub host: Option<IpAddr>,
+    pub port: u16,
+    pub ip_version: IpVersion,
+    /// Origins allowed by CORS, `*` allows any origin and an empty list disables CORS.
+    pub cors_allowed_origins: Vec<String>,
+    pub timeout_secs: u64,
+    /// Seconds `/readyz` fails after SIGINT or SIGTERM before connections are drained,
+    /// so load balancers stop sending requests first.
+    pub drain_secs: u64,
+    /// Maximum number of requests handled at once, extra requests are shed with 503.
+    pub concurrency_limit: Option<usize>,
+    /// Maximum request body size in bytes.
+    pub body_limit: Option<usize>,
+    pub compression: bool,
+    pub trace_level: TraceLevel,
+    pub trace_headers: bool,
+    /// Serve HTTPS instead of plain HTTP.
+    pub tls: Option<TlsConfig>,
+    pub http: HttpSettings,
+    /// Path of the Prometheus endpoint outside the root path, disabled when empty, the
+    /// default. It is served to anyone reaching the API listener and tells route names
+    /// and traffic, keep it from the public, e.g. by not routing it at the ingress.
+    pub metrics_path: Option<String>,
+    /// Format of error responses, clients may still ask for problem details in `Accept`.
+    pub error_format: ErrorFormat,
+    /// Path of the OpenAPI document, served when [`crate::ApiGateway::openapi`] is set.
+    pub openapi_path: String,
+    pub docs_ui: DocsUi,
+    /// Pinned assets of the `docs_ui` page, required when it is set.
+    pub docs_assets: DocsAssets,
+    pub docs_path: String,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: 8080,
+            ip_version: IpVersion::V4,
+            cors_allowed_origins: vec!["*".to_owned()],
+            timeout_secs: 30,
+            drain_secs: 5,
+            concurrency_limit: None,
+            body_limit: None,
+            compression: false,
+            trace_level: TraceLevel::Info,
+            trace_headers: false,
+            tls: None,
+            http: HttpSettings::default(),
+            metrics_path: None,
+            error_format: ErrorFormat::Envelope,
+            openapi_path: "/openapi.json".to_owned(),
+            docs_ui: DocsUi::None,
+            docs_assets: DocsAssets::default(),
+            docs_path: "/docs".to_owned(),
+        }
+    }
+}
+
+impl GatewayConfig {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            ..Default::default()
+        }
+    }
+
+    /// Reads `GATEWAY_*` env variables on top of the defaults: `GATEWAY_HOST`,
+    /// `GATEWAY_PORT`, `GATEWAY_IP_VERSION`, `GATEWAY_CORS_ALLOWED_ORIGINS` (comma
+    /// separated), `GATEWAY_TIMEOUT_SECS`, `GATEWAY_DRAIN_SECS`, `GATEWAY_CONCURRENCY_LIMIT`,
+    /// `GATEWAY_BODY_LIMIT`, `GATEWAY_COMPRESSION`, `GATEWAY_TRACE_LEVEL` and
+    /// `GATEWAY_TRACE_HEADERS`. TLS is enabled by `GATEWAY_TLS_CERT` together with
+    /// `GATEWAY_TLS_KEY`, client certificates by `GATEWAY_TLS_CLIENT_AUTH`
+    /// (`optional` or `required`) and `GATEWAY_TLS_CLIENT_CA`. The [`HttpSettings`]
+    /// fields are read from `GATEWAY_HTTP2`, `GATEWAY_HTTP2_ONLY`,
+    /// `GATEWAY_HTTP2_MAX_CONCURRENT_STREAMS`, `GATEWAY_HTTP2_KEEP_ALIVE_INTERVAL_SECS`,
+    /// `GATEWAY_HTTP2_KEEP_ALIVE_TIMEOUT_SECS`, `GATEWAY_HTTP1_KEEP_ALIVE`,
+    /// `GATEWAY_HEADER_READ_TIMEOUT_SECS` and `GATEWAY_MAX_HEADER_SIZE`.
+    /// `GATEWAY_METRICS_PATH` serves the metrics endpoint, an empty value disables it.
+    /// `GATEWAY_ERROR_FORMAT` is `envelope` or `problem`. The API documentation is set by
+    /// `GATEWAY_OPENAPI_PATH`, `GATEWAY_DOCS_UI` (`swagger` or `redoc`),
+    /// `GATEWAY_DOCS_PATH` and the assets of the page `GATEWAY_DOCS_SCRIPT_URL` with
+    /// `GATEWAY_DOCS_SCRIPT_INTEGRITY` and `GATEWAY_DOCS_STYLESHEET_URL` with
+    /// `GATEWAY_DOCS_STYLESHEET_INTEGRITY`.
+    pub fn from_env() -> Result<Self, AxError> {
+        let mut config = Self::default();
+        if let Some(host) = env_var("GATEWAY_HOST")? {
+            config.host = Some(host);
+        }
+        if let Some(port) = env_var("GATEWAY_PORT")? {
+            config.port = port;
+        }
+        if let Some(ip_version) = env_var("GATEWAY_IP_VERSION")? {
+            config.ip_version = ip_version;
+        }
+        if let Some(origins) = env_var::<String>("GATEWAY_CORS_ALLOWED_ORIGINS")? {
+            config.cors_allowed_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+        if let Some(timeout_secs) = env_var("GATEWAY_TIMEOUT_SECS")? {
+            config.timeout_secs = timeout_secs;
+        }
+        if let Some(drain_secs) = env_var("GATEWAY_DRAIN_SECS")? {
+            config.drain_secs = drain_secs;
+        }
+        if let Some(concurrency_limit) = env_var("GATEWAY_CONCURRENCY_LIMIT")? {
+            config.concurrency_limit = Some(concurrency_limit);
+        }
+        if let Some(body_limit) = env_var("GATEWAY_BODY_LIMIT")? {
+            config.body_limit = Some(body_limit);
+        }
+        if let Some(compression) = env_var("GATEWAY_COMPRESSION")? {
+            config.compression = compression;
+        }
+        if let Some(trace_level) = env_var("GATEWAY_TRACE_LEVEL")? {
+            config.trace_level = trace_level;
+        }
+        if let Some(trace_headers) = env_var("GATEWAY_TRACE_HEADERS")? {
+            config.trace_headers = trace_headers;
+        }
+        match (
+            env_var::<PathBuf>("GATEWAY_TLS_CERT")?,
+            env_var::<PathBuf>("GATEWAY_TLS_KEY")?,
+        ) {
+            (Some(cert_path), Some(key_path)) => {
+                let mut tls = TlsConfig::new(cert_path, key_path);
+                tls.client_ca_path = env_var("GATEWAY_TLS_CLIENT_CA")?;
+                if let Some(client_auth) = env_var("GATEWAY_TLS_CLIENT_AUTH")? {
+                    tls.client_auth = client_auth;
+                }
+                config.tls = Some(tls);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(AxError::ApplicationStartup(
+                    "GATEWAY_TLS_CERT and GATEWAY_TLS_KEY must be set together".to_owned(),
+                ))
+            }
+        }
+        if let Some(http2) = env_var("GATEWAY_HTTP2")? {
+            config.http.http2 = http2;
+        }
+        if let Some(http2_only) = env_var("GATEWAY_HTTP2_ONLY")? {
+            config.http.http2_only = http2_only;
+        }
+        if let Some(streams) = env_var("GATEWAY_HTTP2_MAX_CONCURRENT_STREAMS")? {
+            config.http.http2_max_concurrent_streams = Some(streams);
+        }
+        if let Some(interval) = env_var("GATEWAY_HTTP2_KEEP_ALIVE_INTERVAL_SECS")? {
+            config.http.http2_keep_alive_interval_secs = Some(interval);
+        }
+        if let Some(timeout) = env_var("GATEWAY_HTTP2_KEEP_ALIVE_TIMEOUT_SECS")? {
+            config.http.http2_keep_alive_timeout_secs = timeout;
+        }
+        if let Some(keep_alive) = env_var("GATEWAY_HTTP1_KEEP_ALIVE")? {
+            config.http.http1_keep_alive = keep_alive;
+        }
+        if let Some(timeout) = env_var("GATEWAY_HEADER_READ_TIMEOUT_SECS")? {
+            config.http.header_read_timeout_secs = Some(timeout);
+        }
+        if let Some(size) = env_var("GATEWAY_MAX_HEADER_SIZE")? {
+            config.http.max_header_size = Some(size);
+        }
+        if let Ok(path) = env::var("GATEWAY_METRICS_PATH") {
+            let path = path.trim();
+            config.metrics_path = (!path.is_empty()).then(|| path.to_owned());
+        }
+        if let Some(error_format) = env_var("GATEWAY_ERROR_FORMAT")? {
+            config.error_format = error_format;
+        }
+        if let Some(path) = env_var("GATEWAY_OPENAPI_PATH")? {
+            config.openapi_path = path;
+        }
+        if let Some(docs_ui) = env_var("GATEWAY_DOCS_UI")? {
+            config.docs_ui = docs_ui;
+        }
+        if let Some(path) = env_var("GATEWAY_DOCS_PATH")? {
+            config.docs_path = path;
+        }
+        if let Some(script) = docs_asset("GATEWAY_DOCS_SCRIPT")? {
+            config.docs_assets.script = Some(script);
+        }
+        if let Some(stylesheet) = docs_asset("GATEWAY_DOCS_STYLESHEET")? {
+            config.docs_assets.stylesheet = Some(stylesheet);
+        }
+        Ok(config)
+    }
+
+    /// Reads a TOML file using the field names of this struct.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, AxError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|err| {
+            AxError::ApplicationStartup(format!("failed to read {}: {}", path.display(), err))
+        })?;
+        toml::from_str(&content).map_err(|err| {
+            AxError::ApplicationStartup(format!("invalid config {}: {}", path.display(), err))
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), AxError> {
+        match (self.host, self.ip_version) {
+            (Some(IpAddr::V6(_)), IpVersion::V4) | (Some(IpAddr::V4(_)), IpVersion::V6) => {
+                return Err(AxError::ApplicationStartup(format!(
+                    "host {} does not match ip version {:?}",
+                    self.host.unwrap(),
+                    self.ip_version
+                )))
+            }
+            _ => {}
+        }
+        if self.timeout_secs == 0 {
+            return Err(AxError::ApplicationStartup(
+                "timeout_secs must be greater than 0".to_owned(),
+            ));
+        }
+        if self.concurrency_limit == Some(0) {
+            return Err(AxError::ApplicationStartup(
+                "concurrency_limit must be greater than 0".to_owned(),
+            ));
+        }
+        if self.body_limit == Some(0) {
+            return Err(AxError::ApplicationStartup(
+                "body_limit must be greater than 0".to_owned(),
+            ));
+        }
+        if let Some(path) = &self.metrics_path {
+            if !path.starts_with('/') || path == "/healthz" || path == "/readyz" {
+                return Err(AxError::ApplicationStartup(format!(
+                    "invalid metrics path {}",
+                    path
+                )));
+            }
+        }
+        for path in [&self.openapi_path, &self.docs_path] {
+            if !path.starts_with('/') {
+                return Err(AxError::ApplicationStartup(format!(
+                    "invalid documentation path {}",
+                    path
+                )));
+            }
+        }
+        self.docs_assets.validate(self.docs_ui)?;
+        self.http.validate()?;
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+        }
+        self.allowed_origins().map(|_| ())
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        let host = match (self.host, self.ip_version) {
+            (Some(host), _) => host,
+            (None, IpVersion::V4) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (None, IpVersion::V6) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        SocketAddr::new(host, self.port)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn drain_delay(&self) -> Duration {
+        Duration::from_secs(self.drain_secs)
+    }
+
+    pub fn cors_layer(&self) -> Result<CorsLayer, AxError> {
+        Ok(match self.allowed_origins()? {
+            None => CorsLayer::permissive(),
+            Some(origins) if origins.is_empty() => CorsLayer::new(),
+            Some(origins) => CorsLayer::new()
+                .allow_origin(AllowOrigin::list(origins))
+                .allow_methods(Any)
+                .allow_headers(Any),
+        })
+    }
+
+    /// `None` when any origin is allowed.
+    fn allowed_origins(&self) -> Result<Option<Vec<HeaderValue>>, AxError> {
+        if self.cors_allowed_origins.iter().any(|origin| origin == "*") {
+            if self.cors_allowed_origins.len() > 1 {
+                return Err(AxError::ApplicationStartup(
+                    "cors origin * can not be combined with other origins".to_owned(),
+                ));
+            }
+            return Ok(None);
+        }
+        self.cors_allowed_origins
+            .iter()
+            .map(|origin| {
+                let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                    && !origin.ends_with('/');
+                match HeaderValue::from_str(origin) {
+                    Ok(value) if valid => Ok(value),
+                    _ => Err(AxError::ApplicationStartup(format!(
+                        "invalid cors origin {}",
+                        origin
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+}
+
+fn env_var<T: FromStr>(name: &str) -> Result<Option<T>, AxError>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value.trim().parse::<T>().map(Some).map_err(|err| {
+            AxError::ApplicationStartup(format!("invalid value for {}: {}", name, err))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// `<prefix>_URL` and `<prefix>_INTEGRITY`, which are set together.
+fn docs_asset(prefix: &str) -> Result<Option<DocsAsset>, AxError> {
+    let (url_name, integrity_name) = (format!("{}_URL", prefix), format!("{}_INTEGRITY", prefix));
+    match (
+        env_var::<String>(&url_name)?,
+        env_var::<String>(&integrity_name)?,
+    ) {
+        (Some(url), Some(integrity)) => Ok(Some(DocsAsset { url, integrity })),
+        (None, None) => Ok(None),
+        _ => Err(AxError::ApplicationStartup(format!(
+            "{} and {} must be set together",
+            url_name, integrity_name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        net::{IpAddr, Ipv6Addr},
+        time::Duration,
+    };
+
+    use super::{GatewayConfig, HttpSettings, IpVersion, TraceLevel};
+    use crate::{
+        errors::AxError,
+        openapi::{DocsAsset, DocsAssets, DocsUi},
+        problem::ErrorFormat,
+    };
+
+    #[test]
+    fn test_defaults() {
+        let config = GatewayConfig::new(3000);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.socket_addr().to_string(), "0.0.0.0:3000");
+
+        let config = GatewayConfig {
+            ip_version: IpVersion::V6,
+            ..GatewayConfig::new(3000)
+        };
+        assert_eq!(config.socket_addr().to_string(), "[::]:3000");
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let config: GatewayConfig = toml::from_str(
+            r#"
+            port = 9000
+            host = "127.0.0.1"
+            cors_allowed_origins = ["https://app.example.com"]
+            concurrency_limit = 64
+            drain_secs = 10
+            compression = true
+            trace_level = "debug"
+            error_format = "problem"
+            docs_ui = "swagger"
+
+            [http]
+            http2_only = true
+            http2_max_concurrent_streams = 100
+
+            [docs_assets.script]
+            url = "/assets/swagger-ui-bundle.js"
+            integrity = "sha384-bundle"
+
+            [docs_assets.stylesheet]
+            url = "/assets/swagger-ui.css"
+            integrity = "sha384-stylesheet"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.socket_addr().to_string(), "127.0.0.1:9000");
+        assert_eq!(config.concurrency_limit, Some(64));
+        assert_eq!(config.drain_delay(), Duration::from_secs(10));
+        assert_eq!(config.trace_level, TraceLevel::Debug);
+        assert_eq!(config.timeout_secs, 30);
+        assert_eq!(config.http.http2_max_concurrent_streams, Some(100));
+        assert_eq!(config.http.alpn_protocols(), [b"h2".to_vec()]);
+        assert_eq!(config.error_format, ErrorFormat::Problem);
+        assert_eq!(config.docs_ui, DocsUi::Swagger);
+        assert_eq!(
+            config.docs_assets.script,
+            Some(DocsAsset::new(
+                "/assets/swagger-ui-bundle.js",
+                "sha384-bundle"
+            ))
+        );
+        assert_eq!(config.docs_path, "/docs");
+    }
+
+    #[test]
+    fn test_from_env() {
+        let vars = [
+            ("GATEWAY_PORT", "9000"),
+            (
+                "GATEWAY_CORS_ALLOWED_ORIGINS",
+                "https://a.com, https://b.com,",
+            ),
+            ("GATEWAY_BODY_LIMIT", "1024"),
+            ("GATEWAY_HTTP2_ONLY", "true"),
+            ("GATEWAY_METRICS_PATH", " /metrics "),
+            ("GATEWAY_ERROR_FORMAT", "problem"),
+        ];
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        let config = GatewayConfig::from_env().unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.port, 9000);
+        assert_eq!(
+            config.cors_allowed_origins,
+            ["https://a.com", "https://b.com"]
+        );
+        assert_eq!(config.body_limit, Some(1024));
+        assert!(config.http.http2_only);
+        assert_eq!(config.metrics_path.as_deref(), Some("/metrics"));
+        assert_eq!(config.error_format, ErrorFormat::Problem);
+        assert_eq!(config.timeout_secs, 30);
+
+        env::set_var("GATEWAY_METRICS_PATH", "");
+        assert_eq!(GatewayConfig::from_env().unwrap().metrics_path, None);
+
+        env::set_var("GATEWAY_PORT", "http");
+        assert!(matches!(
+            GatewayConfig::from_env(),
+            Err(AxError::ApplicationStartup(message)) if message.starts_with("invalid value for GATEWAY_PORT")
+        ));
+        env::remove_var("GATEWAY_PORT");
+
+        env::set_var("GATEWAY_DOCS_SCRIPT_URL", "/assets/redoc.js");
+        assert!(matches!(
+            GatewayConfig::from_env(),
+            Err(AxError::ApplicationStartup(_))
+        ));
+        env::remove_var("GATEWAY_DOCS_SCRIPT_URL");
+        for (name, _) in vars {
+            env::remove_var(name);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = [
+            GatewayConfig {
+                host: Some(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+                ..Default::default()
+            },
+            GatewayConfig {
+                timeout_secs: 0,
+                ..Default::default()
+            },
+            GatewayConfig {
+                concurrency_limit: Some(0),
+                ..Default::default()
+            },
+            GatewayConfig {
+                cors_allowed_origins: vec!["*".to_owned(), "https://a.com".to_owned()],
+                ..Default::default()
+            },
+            GatewayConfig {
+                cors_allowed_origins: vec!["a.com".to_owned()],
+                ..Default::default()
+            },
+            GatewayConfig {
+                http: HttpSettings {
+                    http2: false,
+                    http2_only: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            GatewayConfig {
+                metrics_path: Some("metrics".to_owned()),
+                ..Default::default()
+            },
+            GatewayConfig {
+                http: HttpSettings {
+                    max_header_size: Some(1024),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            // the docs page loads nothing unpinned
+            GatewayConfig {
+                docs_ui: DocsUi::Redoc,
+                ..Default::default()
+            },
+            GatewayConfig {
+                docs_ui: DocsUi::Redoc,
+                docs_assets: DocsAssets {
+                    script: Some(DocsAsset::new(
+                        "https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js",
+                        "",
+                    )),
+                    stylesheet: None,
+                },
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(matches!(
+                config.validate(),
+                Err(AxError::ApplicationStartup(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::{fmt, future::Future};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+use crate::{errors::AxError, problem::ErrorFormat};
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client supplied request id that is kept, longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+/// Id of a request, taken from `X-Request-Id` or generated by the gateway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Reuses an id sent by the client when it is printable ASCII of sane length.
+    pub fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?.trim();
+        let valid = !value.is_empty()
+            && value.len() <= MAX_REQUEST_ID_LEN
+            && value.bytes().all(|b| b.is_ascii_graphic());
+        valid.then(|| Self(value.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for RequestId {
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        req.extensions().get::<RequestId>().cloned().ok_or_else(|| {
+            AxError::InternalServerErrorWithContext("request id is not configured".to_owned())
+        })
+    }
+}
+
+/// Per request data available anywhere down the handler's task, e.g. to the
+/// [`AxError`] response. Tasks spawned by the handler do not inherit it.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub request_id: RequestId,
+    /// Path of the request, the problem `instance`.
+    pub path: String,
+    /// Format of the error responses, negotiated with the client.
+    pub error_format: ErrorFormat,
+}
+
+impl RequestContext {
+    /// Context of the request being handled by the current task.
+    pub fn current() -> Option<RequestContext> {
+        CONTEXT.try_with(Clone::clone).ok()
+    }
+
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CONTEXT.scope(self, f).await
+    }
+}
+
+/// Assigns the request id, runs the request inside its [`RequestContext`] and echoes
+/// the id in the `X-Request-Id` response header.
+pub(crate) async fn request_context<B>(
+    mut req: Request<B>,
+    next: Next<B>,
+    error_format: ErrorFormat,
+) -> Response {
+    let request_id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::generate);
+    req.extensions_mut().insert(request_id.clone());
+
+    let context = RequestContext {
+        request_id: request_id.clone(),
+        path: req.uri().path().to_owned(),
+        error_format: error_format.negotiate(req.headers()),
+    };
+    let mut res = context.scope(next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+        res.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{HeaderValue, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use serde_json::Value;
+
+    use super::{RequestId, X_REQUEST_ID};
+    use crate::{
+        errors::AxError,
+        test_util::{self, send},
+        ApiGateway,
+    };
+
+    #[test]
+    fn test_from_header() {
+        let valid = HeaderValue::from_static("req-42");
+        assert_eq!(RequestId::from_header(&valid).unwrap().as_str(), "req-42");
+        for invalid in ["", "has space", &"x".repeat(129)] {
+            let value = HeaderValue::from_str(invalid).unwrap();
+            assert!(RequestId::from_header(&value).is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let routes = Router::new()
+            .route("/id", get(|id: RequestId| async move { id.to_string() }))
+            .route(
+                "/fail",
+                get(|| async { Err::<(), _>(AxError::from(anyhow::anyhow!("boom"))) }),
+            );
+        let app = ApiGateway::new(0, "/api").router(routes).unwrap();
+
+        let req = Request::get("/api/id")
+            .header(&X_REQUEST_ID, "support-123")
+            .body(Body::empty())
+            .unwrap();
+        let res = send(app.clone(), req).await;
+        assert_eq!(res.headers()[&X_REQUEST_ID], "support-123");
+        assert_eq!(&res.body()[..], b"support-123");
+
+        let res = send(app, test_util::get("/api/fail")).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let generated = res.headers()[&X_REQUEST_ID].to_str().unwrap().to_owned();
+        assert_eq!(generated.len(), 36);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["request_id"], generated.as_str());
+    }
+}
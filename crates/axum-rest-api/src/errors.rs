@@ -1,12 +1,21 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
 use thiserror::Error;
 use tracing::error;
 
+use crate::{
+    context::RequestContext,
+    extract::json_rejection_status,
+    problem::{ErrorFormat, ProblemDetails},
+    validation::FieldErrors,
+};
+
 pub type AxResult<T> = Result<AxResponse<T>, AxError>;
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -28,10 +37,30 @@ pub enum AxError {
     NotFound(String),
     #[error("{0}")]
     ApplicationStartup(String),
+    /// A 400 explained by a message. Failed fields come as [`AxError::Validation`].
     #[error("{0}")]
     BadRequest(String),
     #[error("{0}")]
     ObjectConflict(String),
+    /// A 400 listing the failed fields in the `errors` member of the response. It is a
+    /// variant of its own so that `BadRequest(String)` keeps its shape for existing
+    /// callers; match on [`AxError::status`] to handle both.
+    #[error("request validation failed, {0}")]
+    Validation(FieldErrors),
+    #[error("request body is too large")]
+    PayloadTooLarge,
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    #[error("method {0} is not allowed on this resource")]
+    MethodNotAllowed(Method),
+    #[error("request took too long to complete")]
+    RequestTimeout,
+    #[error("service is overloaded, try again later")]
+    ServiceUnavailable,
+    #[error("too many requests, retry in {} seconds", retry_after_secs(.0))]
+    TooManyRequests(Duration),
+    #[error("{0}")]
+    Domain(Box<dyn DomainError>),
     #[error(transparent)]
     AxumJsonRejection(#[from] axum::extract::rejection::JsonRejection),
     #[error("Internal Server error: {0}")]
@@ -40,46 +69,198 @@ pub enum AxError {
     InternalServerErrorWithAnyhow(anyhow::Error),
 }
 
+/// Whole seconds for `Retry-After`, rounded up so clients never retry too early.
+fn retry_after_secs(retry_after: &Duration) -> u64 {
+    let secs = retry_after.as_secs();
+    if retry_after.subsec_nanos() > 0 {
+        secs + 1
+    } else {
+        secs.max(1)
+    }
+}
+
 impl From<anyhow::Error> for AxError {
     fn from(inner: anyhow::Error) -> Self {
         AxError::InternalServerErrorWithAnyhow(inner)
     }
 }
 
+/// Application error with its own code and status, returned from handlers through
+/// [`AxError::Domain`].
+///
+/// ```ignore
+/// #[derive(Debug, thiserror::Error)]
+/// #[error("order {0} is already shipped")]
+/// struct OrderShipped(u64);
+///
+/// impl DomainError for OrderShipped {
+///     fn code(&self) -> &str {
+///         "ORDER_SHIPPED"
+///     }
+///     fn status(&self) -> StatusCode {
+///         StatusCode::CONFLICT
+///     }
+/// }
+///
+/// async fn cancel() -> Result<(), AxError> {
+///     Err(OrderShipped(7))?
+/// }
+/// ```
+pub trait DomainError: std::error::Error + Send + Sync + 'static {
+    /// Stable code clients can match on, e.g. `ORDER_SHIPPED`.
+    fn code(&self) -> &str;
+    fn status(&self) -> StatusCode;
+}
+
+impl<E: DomainError> From<E> for AxError {
+    fn from(err: E) -> Self {
+        AxError::Domain(Box::new(err))
+    }
+}
+
+impl AxError {
+    /// Stable code of the error, rendered next to the message.
+    pub fn code(&self) -> &str {
+        match self {
+            Self::Unauthorized => "AUTH_UNAUTHORIZED",
+            Self::InvalidLoginAttmpt => "AUTH_INVALID_CREDENTIALS",
+            Self::Forbidden => "AUTH_FORBIDDEN",
+            Self::NotFound(_) => "RESOURCE_NOT_FOUND",
+            Self::ApplicationStartup(_) => "APPLICATION_STARTUP",
+            Self::BadRequest(_) => "REQUEST_INVALID",
+            Self::Validation(_) => "REQUEST_VALIDATION_FAILED",
+            Self::ObjectConflict(_) => "RESOURCE_CONFLICT",
+            Self::PayloadTooLarge => "REQUEST_TOO_LARGE",
+            Self::UnsupportedMediaType(_) => "REQUEST_UNSUPPORTED_MEDIA_TYPE",
+            Self::MethodNotAllowed(_) => "METHOD_NOT_ALLOWED",
+            Self::RequestTimeout => "REQUEST_TIMEOUT",
+            Self::ServiceUnavailable => "SERVICE_UNAVAILABLE",
+            Self::TooManyRequests(_) => "RATE_LIMITED",
+            Self::AxumJsonRejection(_) => "REQUEST_INVALID_JSON",
+            Self::Domain(err) => err.code(),
+            Self::InternalServerErrorWithContext(_) | Self::InternalServerErrorWithAnyhow(_) => {
+                "INTERNAL_ERROR"
+            }
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::InvalidLoginAttmpt | Self::BadRequest(_) | Self::Validation(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::ObjectConflict(_) => StatusCode::CONFLICT,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Self::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+            Self::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::AxumJsonRejection(rejection) => json_rejection_status(rejection),
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Domain(err) => err.status(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
 impl IntoResponse for AxError {
     fn into_response(self) -> Response {
         error!("Error Response: {}", self);
-        let (status, error_message) = match self {
+        let retry_after = match &self {
+            Self::TooManyRequests(retry_after) => Some(retry_after_secs(retry_after)),
+            _ => None,
+        };
+        let field_errors = match &self {
+            Self::Validation(errors) => Some(errors.clone()),
+            _ => None,
+        };
+        let status = self.status();
+        let code = self.code().to_owned();
+        let error_message = match self {
             Self::InternalServerErrorWithAnyhow(inner) => {
                 error!("Stacktrace: {}", inner.backtrace());
+                "Opps.. we promise to look at it :( ".to_owned()
+            }
+            Self::NotFound(err)
+            | Self::ObjectConflict(err)
+            | Self::BadRequest(err)
+            | Self::UnsupportedMediaType(err) => err,
+            Self::Validation(_) => "request validation failed".to_owned(),
+            err @ (Self::InvalidLoginAttmpt
+            | Self::Unauthorized
+            | Self::Forbidden
+            | Self::PayloadTooLarge
+            | Self::TooManyRequests(_)
+            | Self::AxumJsonRejection(_)
+            | Self::MethodNotAllowed(_)
+            | Self::RequestTimeout
+            | Self::ServiceUnavailable
+            | Self::Domain(_)) => err.to_string(),
+            _ => "Opps.. we promise to look at it :( ".to_owned(),
+        };
 
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Opps.. we promise to look at it :( ".to_owned(),
-                )
+        let mut res = match ErrorFormat::current() {
+            ErrorFormat::Envelope => {
+                let mut body = AxResponse::err(error_message).with_error_code(&code);
+                if let Some(errors) = field_errors {
+                    body = body.with_errors(errors);
+                }
+                (status, body).into_response()
+            }
+            ErrorFormat::Problem => {
+                let mut problem = ProblemDetails::new(status)
+                    .detail(error_message)
+                    .extension("code", code);
+                if let Some(errors) = field_errors {
+                    problem = problem.extension("errors", json!(errors));
+                }
+                problem.into_response()
             }
-            Self::NotFound(err) => (StatusCode::NOT_FOUND, err),
-            Self::ObjectConflict(err) => (StatusCode::CONFLICT, err),
-            Self::InvalidLoginAttmpt => (
-                StatusCode::BAD_REQUEST,
-                Self::InvalidLoginAttmpt.to_string(),
-            ),
-            Self::Unauthorized => (StatusCode::UNAUTHORIZED, Self::Unauthorized.to_string()),
-            Self::BadRequest(err) => (StatusCode::BAD_REQUEST, err),
-            Self::Forbidden => (StatusCode::FORBIDDEN, Self::Forbidden.to_string()),
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Opps.. we promise to look at it :( ".to_owned(),
-            ),
         };
+        if let Some(secs) = retry_after {
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        res
+    }
+}
+
+/// Renders the errors raised by the gateway middleware instead of a handler: unknown
+/// routes, wrong methods, timeouts, overload and oversized bodies.
+///
+/// ```ignore
+/// let gateway = ApiGateway::new(8080, "/api").on_gateway_error(|err| match err {
+///     AxError::NotFound(_) => Redirect::temporary("/").into_response(),
+///     err => err.into_response(),
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ErrorHook(Arc<dyn Fn(AxError) -> Response + Send + Sync>);
 
-        // let body = Json(ApiResponse::new(error_message));
-        let body = AxResponse::err(error_message);
+impl ErrorHook {
+    pub fn new<F>(hook: F) -> Self
+    where
+        F: Fn(AxError) -> Response + Send + Sync + 'static,
+    {
+        Self(Arc::new(hook))
+    }
 
-        (status, body).into_response()
+    pub fn render(&self, err: AxError) -> Response {
+        (self.0)(err)
     }
 }
 
+impl Default for ErrorHook {
+    fn default() -> Self {
+        Self::new(IntoResponse::into_response)
+    }
+}
+
+/// Envelope of the responses. Built with [`AxResponse::new`] and [`AxResponse::err`],
+/// the error details are private so that adding one does not break callers.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AxResponse<T: Serialize> {
     pub status: ResponseStatus,
@@ -87,6 +268,15 @@ pub struct AxResponse<T: Serialize> {
     pub result: Option<T>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<T>,
+    /// [`AxError::code`] of an error response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error_code: Option<String>,
+    /// Failed fields of [`AxError::Validation`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    errors: Option<FieldErrors>,
+    /// Id of the failed request, to find it in the logs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 impl<T: Serialize> AxResponse<T> {
@@ -95,6 +285,9 @@ impl<T: Serialize> AxResponse<T> {
             status: ResponseStatus::ERROR,
             error_message: Some(data),
             result: None,
+            error_code: None,
+            errors: None,
+            request_id: RequestContext::current().map(|cx| cx.request_id.to_string()),
         }
     }
     pub fn new(data: T) -> Self {
@@ -102,8 +295,33 @@ impl<T: Serialize> AxResponse<T> {
             status: ResponseStatus::OK,
             result: Some(data),
             error_message: None,
+            error_code: None,
+            errors: None,
+            request_id: None,
         }
     }
+
+    pub fn with_error_code(mut self, code: &str) -> Self {
+        self.error_code = Some(code.to_owned());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: FieldErrors) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    pub fn error_code(&self) -> Option<&str> {
+        self.error_code.as_deref()
+    }
+
+    pub fn errors(&self) -> Option<&FieldErrors> {
+        self.errors.as_ref()
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
 }
 
 impl<T: Serialize> IntoResponse for AxResponse<T> {
@@ -111,3 +329,61 @@ impl<T: Serialize> IntoResponse for AxResponse<T> {
         Json(self).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::StatusCode,
+        response::{IntoResponse, Response},
+    };
+    use serde_json::Value;
+    use thiserror::Error;
+
+    use super::{AxError, DomainError};
+    use crate::test_util::read;
+
+    #[derive(Debug, Error)]
+    #[error("order {0} is already shipped")]
+    struct OrderShipped(u64);
+
+    impl DomainError for OrderShipped {
+        fn code(&self) -> &str {
+            "ORDER_SHIPPED"
+        }
+
+        fn status(&self) -> StatusCode {
+            StatusCode::CONFLICT
+        }
+    }
+
+    async fn body(res: Response) -> Value {
+        serde_json::from_slice(read(res).await.body()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_error_codes() {
+        let res = AxError::NotFound("no such item".to_owned()).into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body = body(res).await;
+        assert_eq!(body["error_code"], "RESOURCE_NOT_FOUND");
+        assert_eq!(body["error_message"], "no such item");
+
+        assert_eq!(AxError::Unauthorized.code(), "AUTH_UNAUTHORIZED");
+        let internal = AxError::from(anyhow::anyhow!("boom"));
+        assert_eq!(internal.code(), "INTERNAL_ERROR");
+        assert_eq!(internal.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_domain_error() {
+        let shipped = || -> Result<(), AxError> { Err(OrderShipped(7))? };
+        let err = shipped().unwrap_err();
+        assert_eq!(err.code(), "ORDER_SHIPPED");
+
+        let res = err.into_response();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body = body(res).await;
+        assert_eq!(body["error_code"], "ORDER_SHIPPED");
+        assert_eq!(body["error_message"], "order 7 is already shipped");
+    }
+}
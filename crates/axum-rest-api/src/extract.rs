@@ -0,0 +1,426 @@
+//! Drop-in replacements for the axum extractors that reject with [`AxError`], so
+//! extraction failures are rendered like any other error response.
+
+use std::{error::Error, ops::Deref};
+
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{
+        self,
+        path::ErrorKind,
+        rejection::{
+            BytesRejection, FailedToBufferBody, JsonRejection, PathRejection, TypedHeaderRejection,
+            TypedHeaderRejectionReason,
+        },
+        FromRequest, RequestParts,
+    },
+    headers::Header,
+    http::{header::CONTENT_TYPE, Method},
+    BoxError,
+};
+use http_body::LengthLimitError;
+use serde::de::DeserializeOwned;
+
+use crate::errors::AxError;
+
+/// Path parameters, rejected with the name of the parameter that failed to parse.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    B: Send,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let extract::Path(value) = extract::Path::<T>::from_request(req).await?;
+        Ok(Self(value))
+    }
+}
+
+impl From<PathRejection> for AxError {
+    fn from(rejection: PathRejection) -> Self {
+        let kind = match rejection {
+            PathRejection::FailedToDeserializePathParams(err) => err.into_kind(),
+            rejection => return AxError::InternalServerErrorWithContext(rejection.to_string()),
+        };
+        match kind {
+            ErrorKind::ParseErrorAtKey {
+                key,
+                value,
+                expected_type,
+            } => AxError::BadRequest(format!(
+                "invalid path parameter `{}`: cannot parse {:?} as {}",
+                key, value, expected_type
+            )),
+            ErrorKind::ParseErrorAtIndex {
+                index,
+                value,
+                expected_type,
+            } => AxError::BadRequest(format!(
+                "invalid path parameter {}: cannot parse {:?} as {}",
+                index, value, expected_type
+            )),
+            ErrorKind::ParseError {
+                value,
+                expected_type,
+            } => AxError::BadRequest(format!(
+                "invalid path parameter: cannot parse {:?} as {}",
+                value, expected_type
+            )),
+            ErrorKind::InvalidUtf8InPathParam { key } => {
+                AxError::BadRequest(format!("invalid path parameter `{}`: not UTF-8", key))
+            }
+            // mismatches between the route and the extracted type are bugs
+            kind @ (ErrorKind::WrongNumberOfParameters { .. }
+            | ErrorKind::UnsupportedType { .. }) => {
+                AxError::InternalServerErrorWithContext(kind.to_string())
+            }
+            kind => AxError::BadRequest(format!("invalid path parameters: {}", kind)),
+        }
+    }
+}
+
+/// Query string, rejected with the name of the parameter that failed to parse.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Query<T>
+where
+    T: DeserializeOwned,
+    B: Send,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let query = req.uri().query().unwrap_or_default();
+        Ok(Self(from_urlencoded(query.as_bytes(), "query")?))
+    }
+}
+
+/// `application/x-www-form-urlencoded` body, or the query string of `GET` requests.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Form<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Form<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if req.method() == Method::GET {
+            let query = req.uri().query().unwrap_or_default();
+            return Ok(Self(from_urlencoded(query.as_bytes(), "form")?));
+        }
+        if !has_content_type(req, |mime| mime == "application/x-www-form-urlencoded") {
+            return Err(AxError::UnsupportedMediaType(
+                "expected a form body of type application/x-www-form-urlencoded".to_owned(),
+            ));
+        }
+        let bytes = Bytes::from_request(req).await?;
+        Ok(Self(from_urlencoded(&bytes, "form")?))
+    }
+}
+
+fn from_urlencoded<T: DeserializeOwned>(input: &[u8], source: &str) -> Result<T, AxError> {
+    let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(input));
+    serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        if path == "." {
+            AxError::BadRequest(format!("invalid {} parameters: {}", source, err.inner()))
+        } else {
+            AxError::BadRequest(format!(
+                "invalid {} parameter `{}`: {}",
+                source,
+                path,
+                err.inner()
+            ))
+        }
+    })
+}
+
+/// JSON body, rejected with the path of the field that failed to deserialize.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Json<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let is_json = has_content_type(req, |mime| {
+            mime == "application/json"
+                || (mime.starts_with("application/") && mime.ends_with("+json"))
+        });
+        if !is_json {
+            return Err(AxError::UnsupportedMediaType(
+                "expected a body of type application/json".to_owned(),
+            ));
+        }
+        let bytes = Bytes::from_request(req).await?;
+        let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+        serde_path_to_error::deserialize(deserializer)
+            .map(Self)
+            .map_err(|err| {
+                let path = err.path().to_string();
+                let err = err.into_inner();
+                if err.is_data() && path != "." {
+                    AxError::BadRequest(format!("invalid JSON field `{}`: {}", path, err))
+                } else {
+                    AxError::BadRequest(format!("invalid JSON body: {}", err))
+                }
+            })
+    }
+}
+
+/// Typed header, rejected with the name of the missing or malformed header.
+#[derive(Debug, Clone, Copy)]
+pub struct TypedHeader<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for TypedHeader<T>
+where
+    T: Header,
+    B: Send,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let extract::TypedHeader(value) = extract::TypedHeader::<T>::from_request(req).await?;
+        Ok(Self(value))
+    }
+}
+
+impl From<TypedHeaderRejection> for AxError {
+    fn from(rejection: TypedHeaderRejection) -> Self {
+        match rejection.reason() {
+            TypedHeaderRejectionReason::Missing => {
+                AxError::BadRequest(format!("missing header `{}`", rejection.name()))
+            }
+            reason => AxError::BadRequest(format!(
+                "invalid header `{}`: {:?}",
+                rejection.name(),
+                reason
+            )),
+        }
+    }
+}
+
+impl From<BytesRejection> for AxError {
+    fn from(rejection: BytesRejection) -> Self {
+        match rejection {
+            BytesRejection::FailedToBufferBody(err) if exceeds_body_limit(&err) => {
+                AxError::PayloadTooLarge
+            }
+            BytesRejection::FailedToBufferBody(err) => {
+                AxError::BadRequest(format!("failed to read the request body: {}", err))
+            }
+            rejection => AxError::InternalServerErrorWithContext(rejection.to_string()),
+        }
+    }
+}
+
+/// Status of the `axum::Json` rejections converted into [`AxError`].
+pub(crate) fn json_rejection_status(rejection: &JsonRejection) -> axum::http::StatusCode {
+    use axum::http::StatusCode;
+
+    match rejection {
+        JsonRejection::MissingJsonContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        JsonRejection::BytesRejection(BytesRejection::FailedToBufferBody(err))
+            if exceeds_body_limit(err) =>
+        {
+            StatusCode::PAYLOAD_TOO_LARGE
+        }
+        JsonRejection::BytesRejection(BytesRejection::BodyAlreadyExtracted(_)) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Whether reading the body failed on the gateway's body limit, which reaches the
+/// extractors inside a hyper error.
+fn exceeds_body_limit(err: &FailedToBufferBody) -> bool {
+    if let FailedToBufferBody::LengthLimitError(_) = err {
+        return true;
+    }
+    let mut source = err.source();
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+fn has_content_type<B>(req: &RequestParts<B>, expected: impl Fn(&str) -> bool) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|mime| expected(&mime.trim().to_ascii_lowercase()))
+        .unwrap_or_default()
+}
+
+macro_rules! impl_deref {
+    ($($extractor:ident),*) => {
+        $(
+            impl<T> Deref for $extractor<T> {
+                type Target = T;
+
+                fn deref(&self) -> &T {
+                    &self.0
+                }
+            }
+        )*
+    };
+}
+
+impl_deref!(Path, Query, Form, Json, TypedHeader);
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        headers::UserAgent,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+    use serde::Deserialize;
+
+    use super::{Form, Json, Path, Query, TypedHeader};
+    use crate::test_util::{self, send, send_json};
+
+    #[derive(Debug, Deserialize)]
+    struct Item {
+        id: u32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Search {
+        limit: u32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Order {
+        item: Item,
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/items/:id",
+                get(|Path(item): Path<Item>| async move { item.id.to_string() }),
+            )
+            .route(
+                "/search",
+                get(|Query(search): Query<Search>| async move { search.limit.to_string() }),
+            )
+            .route(
+                "/form",
+                post(|Form(search): Form<Search>| async move { search.limit.to_string() }),
+            )
+            .route(
+                "/orders",
+                post(|Json(order): Json<Order>| async move { order.item.id.to_string() }),
+            )
+            .route(
+                "/agent",
+                get(|TypedHeader(agent): TypedHeader<UserAgent>| async move { agent.to_string() }),
+            )
+    }
+
+    async fn rejection(req: Request<Body>) -> (StatusCode, String) {
+        let (status, body) = send_json(app(), req).await;
+        assert_eq!(body["status"], "ERROR");
+        (status, body["error_message"].as_str().unwrap().to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_rejections() {
+        let get = test_util::get;
+        let post = |uri: &str, content_type: &str, body: &'static str| {
+            Request::post(uri)
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        assert_eq!(
+            rejection(get("/items/abc")).await,
+            (
+                StatusCode::BAD_REQUEST,
+                "invalid path parameter `id`: cannot parse \"abc\" as u32".to_owned()
+            )
+        );
+        assert_eq!(
+            rejection(get("/search?limit=ten")).await,
+            (
+                StatusCode::BAD_REQUEST,
+                "invalid query parameter `limit`: invalid digit found in string".to_owned()
+            )
+        );
+        assert_eq!(
+            rejection(get("/search")).await,
+            (
+                StatusCode::BAD_REQUEST,
+                "invalid query parameters: missing field `limit`".to_owned()
+            )
+        );
+        assert_eq!(
+            rejection(post("/form", "text/plain", "limit=1")).await.0,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            rejection(post(
+                "/form",
+                "application/x-www-form-urlencoded",
+                "limit=-1"
+            ))
+            .await
+            .1,
+            "invalid form parameter `limit`: invalid digit found in string"
+        );
+        assert_eq!(
+            rejection(post("/orders", "application/json", r#"{"item": {"id": "7"}}"#))
+                .await
+                .1,
+            "invalid JSON field `item.id`: invalid type: string \"7\", expected u32 at line 1 column 19"
+        );
+        assert_eq!(
+            rejection(post("/orders", "application/json", "{")).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            rejection(get("/agent")).await,
+            (
+                StatusCode::BAD_REQUEST,
+                "missing header `user-agent`".to_owned()
+            )
+        );
+
+        let req = Request::get("/agent")
+            .header("user-agent", "curl")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(app(), req).await.status(), StatusCode::OK);
+    }
+}
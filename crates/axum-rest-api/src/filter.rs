@@ -0,0 +1,402 @@
+//! `?filter[status]=eq:active&filter[age]=gte:18&sort=-created_at,name`
+//!
+//! A filter is `filter[<field>]=<op>:<value>`, `eq` when the operator is left out. The
+//! operators are `eq`, `ne`, `gt`, `gte`, `lt`, `lte`, `like` and `in`, whose value is a
+//! comma separated list. A prefix which is not an operator is part of the value, e.g.
+//! `filter[url]=https://example.com`. `sort` lists fields in order, `-` sorts a field descending.
+//! Other parameters, e.g. those of [`crate::pagination::Pagination`], are ignored.
+
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+};
+
+use crate::{errors::AxError, validation::FieldErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+/// Fields a listing can be filtered and sorted by.
+///
+/// ```ignore
+/// struct OrderFields;
+///
+/// impl ListFields for OrderFields {
+///     const FILTER: &'static [(&'static str, FieldType)] =
+///         &[("status", FieldType::String), ("total", FieldType::Number)];
+///     const SORT: &'static [&'static str] = &["created_at", "total"];
+/// }
+///
+/// async fn list(query: FilterQuery<OrderFields>, pagination: Pagination) -> AxResult<Page<Order>> { .. }
+/// ```
+pub trait ListFields: Send + Sync + 'static {
+    const FILTER: &'static [(&'static str, FieldType)];
+    const SORT: &'static [&'static str];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Like,
+    In,
+}
+
+impl FromStr for FilterOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eq" => Ok(FilterOp::Eq),
+            "ne" => Ok(FilterOp::Ne),
+            "gt" => Ok(FilterOp::Gt),
+            "gte" => Ok(FilterOp::Gte),
+            "lt" => Ok(FilterOp::Lt),
+            "lte" => Ok(FilterOp::Lte),
+            "like" => Ok(FilterOp::Like),
+            "in" => Ok(FilterOp::In),
+            _ => Err(format!("unknown operator {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for FilterOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FilterOp::Eq => "eq",
+            FilterOp::Ne => "ne",
+            FilterOp::Gt => "gt",
+            FilterOp::Gte => "gte",
+            FilterOp::Lt => "lt",
+            FilterOp::Lte => "lte",
+            FilterOp::Like => "like",
+            FilterOp::In => "in",
+        })
+    }
+}
+
+impl FilterOp {
+    fn applies_to(self, field_type: FieldType) -> bool {
+        match self {
+            FilterOp::Eq | FilterOp::Ne | FilterOp::In => true,
+            FilterOp::Gt | FilterOp::Gte | FilterOp::Lt | FilterOp::Lte => {
+                field_type != FieldType::Boolean
+            }
+            FilterOp::Like => field_type == FieldType::String,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    String(String),
+    Integer(i64),
+    Number(f64),
+    Boolean(bool),
+    /// Values of `in`.
+    List(Vec<FilterValue>),
+}
+
+impl FilterValue {
+    fn parse(value: &str, field_type: FieldType) -> Option<Self> {
+        match field_type {
+            FieldType::String => Some(FilterValue::String(value.to_owned())),
+            FieldType::Integer => value.parse().ok().map(FilterValue::Integer),
+            FieldType::Number => value
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+                .map(FilterValue::Number),
+            FieldType::Boolean => value.parse().ok().map(FilterValue::Boolean),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub field: &'static str,
+    pub op: FilterOp,
+    pub value: FilterValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: &'static str,
+    pub direction: SortDirection,
+}
+
+/// Filters and sort order of a listing, restricted to the fields of `F`. Invalid
+/// parameters are rejected with one [`AxError::Validation`] error per parameter.
+pub struct FilterQuery<F> {
+    pub filters: Vec<Filter>,
+    pub sort: Vec<SortKey>,
+    fields: PhantomData<fn() -> F>,
+}
+
+impl<F> fmt::Debug for FilterQuery<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterQuery")
+            .field("filters", &self.filters)
+            .field("sort", &self.sort)
+            .finish()
+    }
+}
+
+impl<F: ListFields> FilterQuery<F> {
+    pub fn parse(query: &str) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let mut filters = vec![];
+        let mut sort = vec![];
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            if name == "sort" {
+                for key in value.split(',').filter(|key| !key.is_empty()) {
+                    let (name, direction) = match key.strip_prefix('-') {
+                        Some(name) => (name, SortDirection::Desc),
+                        None => (key, SortDirection::Asc),
+                    };
+                    match F::SORT.iter().find(|field| **field == name) {
+                        Some(field) => sort.push(SortKey { field, direction }),
+                        None => {
+                            errors = errors.add(
+                                "sort",
+                                "not_sortable",
+                                &format!("can not sort by {}", name),
+                            )
+                        }
+                    }
+                }
+            } else if let Some(field) = name
+                .strip_prefix("filter[")
+                .and_then(|name| name.strip_suffix(']'))
+            {
+                match Self::filter(field, &value) {
+                    Ok(filter) => filters.push(filter),
+                    Err((code, message)) => errors = errors.add(&name, code, &message),
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self {
+            filters,
+            sort,
+            fields: PhantomData,
+        })
+    }
+
+    fn filter(name: &str, value: &str) -> Result<Filter, (&'static str, String)> {
+        let (field, field_type) = F::FILTER
+            .iter()
+            .find(|(field, _)| *field == name)
+            .copied()
+            .ok_or_else(|| ("unknown_field", format!("can not filter by {}", name)))?;
+        let op = value
+            .split_once(':')
+            .and_then(|(op, value)| Some((op.parse::<FilterOp>().ok()?, value)));
+        let (op, value) = op.unwrap_or((FilterOp::Eq, value));
+        if !op.applies_to(field_type) {
+            return Err((
+                "invalid_operator",
+                format!("operator {} does not apply to {}", op, field),
+            ));
+        }
+        let invalid = || ("invalid_value", format!("invalid value for {}", field));
+        let value = if op == FilterOp::In {
+            FilterValue::List(
+                value
+                    .split(',')
+                    .map(|value| FilterValue::parse(value, field_type).ok_or_else(invalid))
+                    .collect::<Result<_, _>>()?,
+            )
+        } else {
+            FilterValue::parse(value, field_type).ok_or_else(invalid)?
+        };
+        Ok(Filter { field, op, value })
+    }
+}
+
+#[async_trait]
+impl<F: ListFields, B: Send> FromRequest<B> for FilterQuery<F> {
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Self::parse(req.uri().query().unwrap_or_default()).map_err(AxError::Validation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get, Router};
+    use serde_json::json;
+
+    use super::{
+        FieldType, Filter, FilterOp, FilterQuery, FilterValue, ListFields, SortDirection, SortKey,
+    };
+    use crate::{
+        pagination::Pagination,
+        test_util::{self, send_json, send_text},
+    };
+
+    struct OrderFields;
+
+    impl ListFields for OrderFields {
+        const FILTER: &'static [(&'static str, FieldType)] = &[
+            ("status", FieldType::String),
+            ("total", FieldType::Number),
+            ("paid", FieldType::Boolean),
+            ("url", FieldType::String),
+        ];
+        const SORT: &'static [&'static str] = &["created_at", "name"];
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = FilterQuery::<OrderFields>::parse(
+            "filter[status]=eq:active&filter[total]=gte:10.5&filter[paid]=true\
+             &filter[status]=in:new,paid&sort=-created_at,name&page=2",
+        )
+        .unwrap();
+        assert_eq!(
+            query.filters,
+            [
+                Filter {
+                    field: "status",
+                    op: FilterOp::Eq,
+                    value: FilterValue::String("active".to_owned()),
+                },
+                Filter {
+                    field: "total",
+                    op: FilterOp::Gte,
+                    value: FilterValue::Number(10.5),
+                },
+                Filter {
+                    field: "paid",
+                    op: FilterOp::Eq,
+                    value: FilterValue::Boolean(true),
+                },
+                Filter {
+                    field: "status",
+                    op: FilterOp::In,
+                    value: FilterValue::List(vec![
+                        FilterValue::String("new".to_owned()),
+                        FilterValue::String("paid".to_owned()),
+                    ]),
+                },
+            ]
+        );
+        assert_eq!(
+            query.sort,
+            [
+                SortKey {
+                    field: "created_at",
+                    direction: SortDirection::Desc,
+                },
+                SortKey {
+                    field: "name",
+                    direction: SortDirection::Asc,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_values_with_colons() {
+        let query = FilterQuery::<OrderFields>::parse(
+            "filter[url]=https://example.com&filter[status]=10:30&filter[url]=ne:a:b",
+        )
+        .unwrap();
+        let filters: Vec<_> = query
+            .filters
+            .into_iter()
+            .map(|filter| (filter.op, filter.value))
+            .collect();
+        assert_eq!(
+            filters,
+            [
+                (
+                    FilterOp::Eq,
+                    FilterValue::String("https://example.com".to_owned())
+                ),
+                (FilterOp::Eq, FilterValue::String("10:30".to_owned())),
+                (FilterOp::Ne, FilterValue::String("a:b".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_finite_numbers() {
+        for value in ["NaN", "inf", "-infinity", "gt:nan"] {
+            let query = format!("filter[total]={}", value);
+            assert!(
+                FilterQuery::<OrderFields>::parse(&query).is_err(),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_filters() {
+        let app = Router::new().route(
+            "/orders",
+            get(
+                |query: FilterQuery<OrderFields>, pagination: Pagination| async move {
+                    format!("{} {}", query.filters.len(), pagination.limit())
+                },
+            ),
+        );
+
+        let req = test_util::get("/orders?filter[status]=active&per_page=5");
+        let (_, body) = send_text(app.clone(), req).await;
+        assert_eq!(body, "1 5");
+
+        let req = test_util::get(
+            "/orders?filter[owner]=bob&filter[total]=like:9&filter[paid]=maybe&sort=total",
+        );
+        let (status, body) = send_json(app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["errors"],
+            json!([
+                {
+                    "field": "filter[owner]",
+                    "code": "unknown_field",
+                    "message": "can not filter by owner"
+                },
+                {
+                    "field": "filter[total]",
+                    "code": "invalid_operator",
+                    "message": "operator like does not apply to total"
+                },
+                {
+                    "field": "filter[paid]",
+                    "code": "invalid_value",
+                    "message": "invalid value for paid"
+                },
+                {
+                    "field": "sort",
+                    "code": "not_sortable",
+                    "message": "can not sort by total"
+                }
+            ])
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait, http::StatusCode, response::IntoResponse, routing::get, BoxError, Extension, Json,
+    Router,
+};
+use futures_util::future::join_all;
+use keycloak::KeycloakClient;
+use serde::Serialize;
+
+/// Checks are failed when they take longer than this.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A dependency probed by `/readyz`, e.g. a database ping.
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Key of the check in the JSON output.
+    fn name(&self) -> &str;
+
+    /// Also run the check on `/healthz`. A failing liveness probe gets the process
+    /// restarted, so only mark checks a restart can fix.
+    fn liveness(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Result<(), BoxError>;
+}
+
+/// Readiness of Keycloak, checked with [`keycloak::Keycloak::ping`].
+pub struct KeycloakHealthCheck {
+    keycloak: KeycloakClient,
+}
+
+impl KeycloakHealthCheck {
+    pub fn new(keycloak: KeycloakClient) -> Self {
+        Self { keycloak }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for KeycloakHealthCheck {
+    fn name(&self) -> &str {
+        "keycloak"
+    }
+
+    async fn check(&self) -> Result<(), BoxError> {
+        self.keycloak.ping().await?;
+        Ok(())
+    }
+}
+
+/// Registered checks and the readiness flag behind `/healthz` and `/readyz`.
+#[derive(Clone)]
+pub struct Health {
+    checks: Vec<Arc<dyn HealthCheck>>,
+    ready: Arc<AtomicBool>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            checks: vec![],
+            ready: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Health")
+            .field(
+                "checks",
+                &self.checks.iter().map(|c| c.name()).collect::<Vec<_>>(),
+            )
+            .field("ready", &self.is_ready())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Error,
+    ShuttingDown,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub status: HealthStatus,
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+impl IntoResponse for HealthReport {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self.status {
+            HealthStatus::Ok => StatusCode::OK,
+            _ => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check<C: HealthCheck + 'static>(mut self, check: C) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Flips `/readyz`, done by the gateway when graceful shutdown starts.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// Runs the liveness checks, or all checks for readiness.
+    pub async fn report(&self, readiness: bool) -> HealthReport {
+        let checks = self
+            .checks
+            .iter()
+            .filter(|check| readiness || check.liveness())
+            .map(|check| async move {
+                let start = Instant::now();
+                let res = match tokio::time::timeout(CHECK_TIMEOUT, check.check()).await {
+                    Ok(res) => res,
+                    Err(_) => Err("check timed out".into()),
+                };
+                let report = CheckReport {
+                    status: if res.is_ok() {
+                        HealthStatus::Ok
+                    } else {
+                        HealthStatus::Error
+                    },
+                    duration_ms: start.elapsed().as_millis(),
+                    error: res.err().map(|err| err.to_string()),
+                };
+                (check.name().to_owned(), report)
+            });
+        let checks: BTreeMap<_, _> = join_all(checks).await.into_iter().collect();
+
+        let status = if checks.values().all(|c| c.status == HealthStatus::Ok) {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Error
+        };
+        HealthReport { status, checks }
+    }
+
+    pub(crate) fn routes(&self) -> Router {
+        Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .layer(Extension(self.clone()))
+    }
+}
+
+async fn healthz(Extension(health): Extension<Health>) -> HealthReport {
+    health.report(false).await
+}
+
+async fn readyz(Extension(health): Extension<Health>) -> HealthReport {
+    if !health.is_ready() {
+        // no point probing dependencies while draining
+        return HealthReport {
+            status: HealthStatus::ShuttingDown,
+            checks: BTreeMap::new(),
+        };
+    }
+    health.report(true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{async_trait, http::StatusCode, routing::get, BoxError, Router};
+    use keycloak::testing::{Failure, MockKeycloak};
+    use tokio::sync::Notify;
+
+    use super::{HealthCheck, KeycloakHealthCheck};
+    use crate::{
+        config::GatewayConfig,
+        test_util::{self, send, send_json},
+        ApiGateway,
+    };
+
+    struct Database {
+        up: bool,
+    }
+
+    #[async_trait]
+    impl HealthCheck for Database {
+        fn name(&self) -> &str {
+            "database"
+        }
+
+        async fn check(&self) -> Result<(), BoxError> {
+            if self.up {
+                Ok(())
+            } else {
+                Err("connection refused".into())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probes() {
+        let gateway = ApiGateway::new(0, "/api").health_check(Database { up: false });
+        let app = gateway.router(Router::new()).unwrap();
+
+        let (status, body) = send_json(app.clone(), test_util::get("/healthz")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+
+        let (status, body) = send_json(app.clone(), test_util::get("/readyz")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["checks"]["database"]["error"], "connection refused");
+
+        // probes live outside the root path
+        let res = send(app, test_util::get("/api/readyz")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_probes_are_not_limited() {
+        let config = GatewayConfig {
+            concurrency_limit: Some(1),
+            ..Default::default()
+        };
+        let (started, mut wait_started) = tokio::sync::mpsc::channel(1);
+        let release = Arc::new(Notify::new());
+        let handler_release = release.clone();
+        let routes = Router::new().route(
+            "/slow",
+            get(move || async move {
+                started.send(()).await.unwrap();
+                handler_release.notified().await;
+            }),
+        );
+        let app = ApiGateway::with_config(config, "/api")
+            .router(routes)
+            .unwrap();
+
+        let slow = tokio::spawn(send(app.clone(), test_util::get("/api/slow")));
+        wait_started.recv().await.unwrap();
+        let res = send(app.clone(), test_util::get("/api/slow")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        for probe in ["/healthz", "/readyz"] {
+            let res = send(app.clone(), test_util::get(probe)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        release.notify_one();
+        assert_eq!(slow.await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_while_shutting_down() {
+        let gateway = ApiGateway::new(0, "/api").health_check(Database { up: true });
+        let app = gateway.router(Router::new()).unwrap();
+        assert_eq!(
+            send_json(app.clone(), test_util::get("/readyz")).await.0,
+            StatusCode::OK
+        );
+
+        gateway.health().set_ready(false);
+        let (status, body) = send_json(app.clone(), test_util::get("/readyz")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "shutting_down");
+        assert_eq!(
+            send_json(app.clone(), test_util::get("/healthz")).await.0,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keycloak_health_check() {
+        let server = MockKeycloak::start().await;
+        let keycloak = Arc::new(server.client());
+        let check = KeycloakHealthCheck::new(keycloak);
+        assert!(check.check().await.is_ok());
+
+        // probes are not retried
+        server.fail_always(Failure::Unavailable(None));
+        assert!(check.check().await.is_err());
+        assert_eq!(server.request_count("/certs"), 2);
+    }
+}
@@ -1,50 +1,390 @@
+pub mod auth;
+pub mod config;
+pub mod context;
 pub mod errors;
+pub mod extract;
+pub mod filter;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
+pub mod pagination;
+pub mod problem;
+pub mod ratelimit;
 pub mod routes;
-use anyhow::Result;
+pub mod state;
+pub mod telemetry;
+#[cfg(test)]
+mod test_util;
+pub mod tls;
+pub mod validation;
+pub mod versioning;
 use axum::{
-    error_handling::HandleErrorLayer, handler::Handler, http::StatusCode, response::IntoResponse,
-    BoxError, Router,
+    body::{Body, HttpBody},
+    error_handling::HandleErrorLayer,
+    handler::Handler,
+    http::{
+        header::{ALLOW, CONTENT_TYPE},
+        Extensions, HeaderMap, Request, StatusCode, Uri, Version,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    BoxError, Extension, Router,
 };
-use std::{net::SocketAddr, time::Duration};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use config::{GatewayConfig, HttpSettings};
+use errors::{AxError, ErrorHook};
+use futures_util::stream;
+use health::{Health, HealthCheck};
+use http_body::Limited;
+use metrics::Metrics;
+use routes::RouteModule;
+use state::{AppState, StateKey};
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    backtrace::BacktraceStatus,
+    cell::{Cell, RefCell},
+    future::{poll_fn, Future},
+    net::{SocketAddr, TcpListener},
+    panic,
+    sync::{Mutex, Once, PoisonError},
+};
+use telemetry::MakeRequestSpan;
+use tls::ClientCertAcceptor;
 use tokio::signal;
-use tower::ServiceBuilder;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder};
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    compression::{predicate::DefaultPredicate, CompressionLayer, Predicate},
+    limit::RequestBodyLimitLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::error;
+use utoipa::openapi::OpenApi;
+use versioning::ApiVersions;
 
+/// Serves the routes of the service behind the gateway middleware. A handler panic is
+/// logged and answered with a 500, call [`capture_panic_traces`] once at startup to log
+/// where it happened.
 pub struct ApiGateway<'a> {
-    port: u16,
+    config: GatewayConfig,
     root_path: &'a str,
+    health: Health,
+    metrics: Metrics,
+    error_hook: ErrorHook,
+    openapi: Option<OpenApi>,
+    modules: Vec<Box<dyn RouteModule>>,
+    state: AppState,
+    required_state: Vec<StateKey>,
+    /// In a mutex as `Router` is not `Sync`, which `serve` futures need the gateway to be.
+    versions: Option<Mutex<ApiVersions>>,
 }
 
 impl<'a> ApiGateway<'a> {
     pub fn new(port: u16, root_path: &'a str) -> Self {
-        Self { port, root_path }
+        Self::with_config(GatewayConfig::new(port), root_path)
+    }
+
+    pub fn with_config(config: GatewayConfig, root_path: &'a str) -> Self {
+        Self {
+            config,
+            root_path,
+            health: Health::new(),
+            metrics: Metrics::new(),
+            error_hook: ErrorHook::default(),
+            openapi: None,
+            modules: vec![],
+            state: AppState::new(),
+            required_state: vec![],
+            versions: None,
+        }
+    }
+
+    /// Replaces the HTTP/1 and HTTP/2 connection settings of the config.
+    pub fn http_settings(mut self, http: HttpSettings) -> Self {
+        self.config.http = http;
+        self
+    }
+
+    /// Registers a check run by `/readyz` (and `/healthz` for liveness checks).
+    pub fn health_check<C: HealthCheck + 'static>(mut self, check: C) -> Self {
+        self.health = self.health.check(check);
+        self
+    }
+
+    /// Customises the responses of the errors raised by the gateway middleware, see
+    /// [`ErrorHook`].
+    pub fn on_gateway_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(AxError) -> Response + Send + Sync + 'static,
+    {
+        self.error_hook = ErrorHook::new(hook);
+        self
+    }
+
+    /// Serves `doc`, e.g. `ApiDoc::openapi()` of a `#[derive(utoipa::OpenApi)]`, on
+    /// [`GatewayConfig::openapi_path`]. Its paths are relative to the root path.
+    pub fn openapi(mut self, doc: OpenApi) -> Self {
+        self.openapi = Some(doc);
+        self
+    }
+
+    /// Serves the routes of `module` below the root path, next to the routes passed to
+    /// [`ApiGateway::router`], see [`RouteModule`].
+    pub fn module<M: RouteModule>(mut self, module: M) -> Self {
+        self.modules.push(Box::new(module));
+        self
+    }
+
+    /// Serves the versions next to the routes passed to [`ApiGateway::router`], see
+    /// [`ApiVersions`].
+    pub fn versions(mut self, versions: ApiVersions) -> Self {
+        self.versions = Some(Mutex::new(versions));
+        self
+    }
+
+    /// Shares `value` with the handlers, see [`state::State`]. The [`GatewayConfig`] is
+    /// always shared.
+    pub fn state<T: Clone + Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.state.insert(value);
+        self
+    }
+
+    /// Makes [`ApiGateway::router`] fail when no state of type `T` is set.
+    pub fn require_state<T: 'static>(mut self) -> Self {
+        self.required_state.push(StateKey::of::<T>());
+        self
+    }
+
+    pub fn health(&self) -> &Health {
+        &self.health
+    }
+
+    /// Registry served on [`GatewayConfig::metrics_path`], register service metrics here.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn config(&self) -> &GatewayConfig {
+        &self.config
+    }
+
+    /// Serves HTTPS when [`GatewayConfig::tls`] is set and plain HTTP otherwise, until
+    /// SIGINT or SIGTERM, then fails `/readyz` for [`GatewayConfig::drain_secs`] before
+    /// draining the connections.
+    pub async fn serve(&self, routes: Router) -> Result<(), AxError> {
+        let app = self.router(routes)?;
+        let addr = self.config.socket_addr();
+        let listener = TcpListener::bind(addr).map_err(|err| {
+            AxError::ApplicationStartup(format!("failed to bind {}: {}", addr, err))
+        })?;
+        let handle = Handle::new();
+        let shutdown = handle.clone();
+        let health = self.health.clone();
+        let drain_delay = self.config.drain_delay();
+        tokio::spawn(async move {
+            shutdown_signal(health).await;
+            // load balancers need a few probes to see `/readyz` fail
+            tokio::time::sleep(drain_delay).await;
+            shutdown.graceful_shutdown(None);
+        });
+        self.serve_listener(listener, app, handle).await
     }
 
-    pub async fn serve(&self, routes: Router, timeout: u64) -> Result<()> {
+    async fn serve_listener(
+        &self,
+        listener: TcpListener,
+        app: Router,
+        handle: Handle,
+    ) -> Result<(), AxError> {
+        let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+        let server = axum_server::from_tcp(listener)
+            .handle(handle)
+            .http_config(self.config.http.http_config());
+        let res = match &self.config.tls {
+            Some(tls) => {
+                let alpn_protocols = self.config.http.alpn_protocols();
+                let rustls = RustlsConfig::from_config(tls.build(alpn_protocols.clone())?);
+                // certificates are reloaded until the server stops
+                let _watch = tls.clone().watch(rustls.clone(), alpn_protocols);
+                server
+                    .acceptor(ClientCertAcceptor::new(rustls))
+                    .serve(make_service)
+                    .await
+            }
+            None => server.serve(make_service).await,
+        };
+        res.map_err(|err| AxError::InternalServerErrorWithContext(err.to_string()))
+    }
+
+    /// The application `serve` runs: `routes` nested under the root path, behind the
+    /// gateway middleware. Useful to test the whole stack without binding a port.
+    ///
+    /// Fails when the prefixes of route modules and API versions overlap. `routes` are
+    /// not checked against them, a conflicting route panics as in [`Router::merge`].
+    pub fn router(&self, routes: Router) -> Result<Router, AxError> {
+        self.config.validate()?;
+        let mut state = self.state.clone();
+        state.insert(self.config.clone());
+        state.check(&self.required_state, "gateway")?;
+
+        let trace_level = self.config.trace_level.into();
+        let compression = self.config.compression;
+        let body_limit = self.config.body_limit;
+        let metrics = self.metrics.clone();
+        let rejections = self.metrics.clone();
+        let panics = self.metrics.clone();
+        let error_format = self.config.error_format;
+        let hook = self.error_hook.clone();
+        let (method_hook, limit_hook, fallback_hook, version_hook) =
+            (hook.clone(), hook.clone(), hook.clone(), hook.clone());
         let middleware = ServiceBuilder::new()
-            .layer(TraceLayer::new_for_http())
-            .layer(CorsLayer::permissive())
-            .layer(HandleErrorLayer::new(handle_error))
-            .timeout(Duration::from_secs(timeout))
+            .layer(Extension(state.clone()))
+            .layer(middleware::from_fn(move |req, next| {
+                context::request_context(req, next, error_format)
+            }))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(MakeRequestSpan::new(trace_level, self.config.trace_headers))
+                    .on_response(
+                        DefaultOnResponse::new()
+                            .level(trace_level)
+                            .include_headers(self.config.trace_headers),
+                    ),
+            )
+            .layer(CompressionLayer::new().compress_when(
+                DefaultPredicate::new().and(
+                    move |_: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| compression,
+                ),
+            ))
+            .layer(self.config.cors_layer()?)
+            .layer(middleware::from_fn(move |req, next| {
+                metrics::track(req, next, metrics.clone())
+            }))
+            .layer(CatchPanicLayer::custom(move |panic| {
+                panics.record_panic();
+                panic_response(panic)
+            }))
+            .layer(middleware::from_fn(catching_panics))
+            .layer(middleware::from_fn(move |req, next| {
+                method_not_allowed(req, next, method_hook.clone())
+            }))
+            .into_inner();
+        // not applied to the probes, an overloaded instance should shed requests rather
+        // than fail liveness and get restarted
+        let limits = ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(move |err: BoxError| {
+                rejections.record_rejection(&err);
+                let hook = hook.clone();
+                async move { hook.render(handle_error(err).await) }
+            }))
+            .timeout(self.config.timeout())
             .load_shed()
+            // `Router::layer` wraps every route, the semaphore is shared to cap them all
+            .option_layer(
+                self.config
+                    .concurrency_limit
+                    .map(GlobalConcurrencyLimitLayer::new),
+            )
+            .option_layer(body_limit.map(|limit| {
+                ServiceBuilder::new()
+                    .layer(middleware::from_fn(move |req, next| {
+                        payload_too_large(req, next, limit_hook.clone())
+                    }))
+                    .layer(RequestBodyLimitLayer::new(limit))
+                    .map_request(|req: Request<Limited<Body>>| req.map(limited_body))
+            }))
+            .layer(middleware::from_fn(move |req, next| {
+                versioning::selected_version(req, next, version_hook.clone())
+            }))
             .into_inner();
 
-        let app = Router::new()
+        let mut router = Router::new();
+        if let Some(path) = &self.config.metrics_path {
+            router = router.merge(self.metrics.routes(path));
+        }
+        if let Some(doc) = &self.openapi {
+            let mut doc = doc.clone();
+            for module in &self.modules {
+                if let Some(module_doc) = routes::module_openapi(module.as_ref()) {
+                    doc.merge(module_doc);
+                }
+            }
+            let doc = openapi::document(doc, self.root_path);
+            router = router.merge(openapi::routes(
+                &doc,
+                &self.config.openapi_path,
+                self.config.docs_ui,
+                &self.config.docs_assets,
+                &self.config.docs_path,
+            )?);
+        }
+        let versions = self.versions.as_ref().map(|versions| {
+            versions
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+        });
+        let mut routes = routes::compose(&self.modules, &state, routes)?;
+        if let Some(versions) = &versions {
+            for name in versions.names() {
+                let what = format!("API version {}", name);
+                routes::check_prefix(&self.modules, &format!("/{}", name), &what)?;
+            }
+            routes = routes.merge(versions.routes()?);
+        }
+        let app = router
             .nest(self.root_path, routes)
-            .fallback(handler_404.into_service())
+            .fallback(
+                (move |uri: Uri| async move { fallback_hook.render(handler_404(uri).await) })
+                    .into_service(),
+            )
+            .layer(limits)
+            .merge(self.health.routes())
             .layer(middleware);
+        Ok(match versions {
+            Some(versions) => versions.select(app, self.root_path),
+            None => app,
+        })
+    }
+}
 
-        let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
-        axum::Server::bind(&addr)
-            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-            .with_graceful_shutdown(shutdown_signal())
-            .await
-            .unwrap();
-        Ok(())
+/// Hands the body limited by [`RequestBodyLimitLayer`] to the routes, which take a
+/// hyper `Body`. Extractors of [`crate::extract`] reject a body over the limit with 413.
+fn limited_body(body: Limited<Body>) -> Body {
+    Body::wrap_stream(stream::unfold(body, |mut body| async move {
+        body.data().await.map(|chunk| (chunk, body))
+    }))
+}
+
+/// Renders the plain text `413` of [`RequestBodyLimitLayer`] with the error hook.
+async fn payload_too_large<B>(req: Request<B>, next: Next<B>, hook: ErrorHook) -> Response {
+    let res = next.run(req).await;
+    let plain_text = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/plain"));
+    if res.status() == StatusCode::PAYLOAD_TOO_LARGE && plain_text {
+        return hook.render(AxError::PayloadTooLarge);
     }
+    res
 }
 
-async fn shutdown_signal() {
+/// Replaces the empty `405` responses of axum's method routers, keeping `Allow`.
+async fn method_not_allowed<B>(req: Request<B>, next: Next<B>, hook: ErrorHook) -> Response {
+    let method = req.method().clone();
+    let res = next.run(req).await;
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED || res.headers().contains_key(CONTENT_TYPE) {
+        return res;
+    }
+    let mut error = hook.render(AxError::MethodNotAllowed(method));
+    if let Some(allow) = res.headers().get(ALLOW) {
+        error.headers_mut().insert(ALLOW, allow.clone());
+    }
+    error
+}
+
+async fn shutdown_signal(health: Health) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -63,22 +403,371 @@ async fn shutdown_signal() {
         _ = ctrl_c => {},
         _ = terminate => {},
     }
+    health.set_ready(false);
+}
+
+thread_local! {
+    /// Where the last panic of the thread happened, taken by [`panic_response`].
+    static PANIC_TRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Whether a panic of the thread would be caught by [`CatchPanicLayer`].
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Chains a panic hook to the process which keeps the location of a handler panic,
+/// gone once [`CatchPanicLayer`] caught it, for the error log of the gateway. The
+/// backtrace is captured as `RUST_BACKTRACE` says, like the ones of
+/// [`AxError::InternalServerErrorWithAnyhow`]. Handler panics are not passed to the
+/// previous hook as they are logged anyway, other panics are.
+pub fn capture_panic_traces() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING_PANICS.with(Cell::get) {
+                return previous(info);
+            }
+            let location = info
+                .location()
+                .map(|location| location.to_string())
+                .unwrap_or_else(|| "unknown location".to_owned());
+            let backtrace = Backtrace::capture();
+            let trace = match backtrace.status() {
+                BacktraceStatus::Captured => format!("at {}\n{}", location, backtrace),
+                _ => format!("at {}", location),
+            };
+            PANIC_TRACE.with(|last| *last.borrow_mut() = Some(trace));
+        }));
+    });
+}
+
+/// Marks the thread while it polls the inner services, whose panics [`CatchPanicLayer`]
+/// catches.
+async fn catching_panics<B>(req: Request<B>, next: Next<B>) -> Response {
+    struct Catching(bool);
+
+    impl Drop for Catching {
+        fn drop(&mut self) {
+            CATCHING_PANICS.with(|catching| catching.set(self.0));
+        }
+    }
+
+    let mut res = Box::pin(next.run(req));
+    poll_fn(move |cx| {
+        let _catching = Catching(CATCHING_PANICS.with(|catching| catching.replace(true)));
+        res.as_mut().poll(cx)
+    })
+    .await
+}
+
+/// Logs a handler panic, with its location when [`capture_panic_traces`] kept it, and
+/// answers with a 500.
+fn panic_response(panic: Box<dyn Any + Send>) -> Response {
+    let message = panic_message(panic.as_ref());
+    // `CatchPanicLayer` calls this on the thread which panicked
+    let trace = PANIC_TRACE
+        .with(|last| last.borrow_mut().take())
+        .unwrap_or_else(|| "at unknown location".to_owned());
+    error!("handler panicked: {} {}", message, trace);
+    AxError::InternalServerErrorWithContext(format!("handler panicked: {}", message))
+        .into_response()
 }
 
-pub async fn handle_error(error: BoxError) -> (StatusCode, String) {
-    let mut code = StatusCode::INTERNAL_SERVER_ERROR;
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic payload".to_owned())
+}
 
+pub async fn handle_error(error: BoxError) -> AxError {
     if error.is::<tower::timeout::error::Elapsed>() {
-        code = StatusCode::REQUEST_TIMEOUT;
+        return AxError::RequestTimeout;
     }
 
     if error.is::<tower::load_shed::error::Overloaded>() {
-        code = StatusCode::SERVICE_UNAVAILABLE;
+        return AxError::ServiceUnavailable;
     }
 
-    (code, "Internal Error".to_owned())
+    AxError::InternalServerErrorWithContext(error.to_string())
 }
 
-pub async fn handler_404() -> impl IntoResponse {
-    (StatusCode::NOT_FOUND, "nothing to see here")
+pub async fn handler_404(uri: Uri) -> AxError {
+    AxError::NotFound(format!("no resource at {}", uri.path()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{Body, Bytes},
+        http::{
+            header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE},
+            Request, StatusCode, Version,
+        },
+        response::IntoResponse,
+        routing::{get, post},
+        Router,
+    };
+    use axum_server::Handle;
+    use serde_json::Value;
+    use std::{
+        fmt::{self, Write},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::net::TcpStream;
+    use tracing::{
+        field::{Field, Visit},
+        Event, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+    use crate::{
+        capture_panic_traces,
+        config::{GatewayConfig, HttpSettings},
+        errors::AxError,
+        extract::Json,
+        test_util::{self, send, send_json},
+        ApiGateway, PANIC_TRACE,
+    };
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let config = GatewayConfig {
+            body_limit: Some(8),
+            ..Default::default()
+        };
+        let routes = Router::new()
+            .route("/echo", post(|body: Bytes| async move { body }))
+            .route(
+                "/json",
+                post(|Json(value): Json<Value>| async move { value.to_string() }),
+            );
+        let app = ApiGateway::with_config(config, "/api")
+            .router(routes)
+            .unwrap();
+
+        let req = Request::post("/api/echo")
+            .body(Body::from("small"))
+            .unwrap();
+        assert_eq!(send(app.clone(), req).await.status(), StatusCode::OK);
+
+        let (status, body) = send_json(
+            app.clone(),
+            Request::post("/api/echo")
+                .header(CONTENT_LENGTH, 21)
+                .body(Body::from("a body over the limit"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error_code"], "REQUEST_TOO_LARGE");
+
+        // without content-length the limit applies while the body is read
+        let (status, body) = send_json(
+            app,
+            Request::post("/api/json")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(r#""a body over the limit""#))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error_code"], "REQUEST_TOO_LARGE");
+    }
+
+    #[tokio::test]
+    async fn test_gateway_errors() {
+        let config = GatewayConfig {
+            timeout_secs: 1,
+            ..Default::default()
+        };
+        let routes = Router::new().route(
+            "/slow",
+            get(|| async { tokio::time::sleep(Duration::from_secs(5)).await }),
+        );
+        let app = ApiGateway::with_config(config, "/api")
+            .router(routes)
+            .unwrap();
+
+        let (status, body) = send_json(app.clone(), test_util::get("/api/missing")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error_message"], "no resource at /api/missing");
+
+        let req = Request::delete("/api/slow").body(Body::empty()).unwrap();
+        let res = send(app.clone(), req).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "GET,HEAD");
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["error_code"], "METHOD_NOT_ALLOWED");
+
+        let (status, body) = send_json(app, test_util::get("/api/slow")).await;
+        assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(body["error_message"], "request took too long to complete");
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let config = GatewayConfig {
+            concurrency_limit: Some(1),
+            ..Default::default()
+        };
+        let (started, mut wait_started) = tokio::sync::mpsc::channel::<()>(1);
+        let release = Arc::new(tokio::sync::Notify::new());
+        let handler_release = release.clone();
+        let routes = Router::new()
+            .route(
+                "/slow",
+                get(move || async move {
+                    started.send(()).await.unwrap();
+                    handler_release.notified().await;
+                }),
+            )
+            .route("/fast", get(|| async { "fast" }));
+        let app = ApiGateway::with_config(config, "/api")
+            .router(routes)
+            .unwrap();
+
+        let slow = tokio::spawn(send(app.clone(), test_util::get("/api/slow")));
+        wait_started.recv().await.unwrap();
+        let (status, body) = send_json(app, test_util::get("/api/fast")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error_code"], "SERVICE_UNAVAILABLE");
+
+        release.notify_one();
+        assert_eq!(slow.await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_error_hook() {
+        let app = ApiGateway::new(0, "/api")
+            .on_gateway_error(|err| match err {
+                AxError::NotFound(_) => {
+                    AxError::NotFound("try /api/items".to_owned()).into_response()
+                }
+                err => err.into_response(),
+            })
+            .router(Router::new())
+            .unwrap();
+        let (status, body) = send_json(app, test_util::get("/api/nope")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error_message"], "try /api/items");
+    }
+
+    /// Messages of the events logged while it is the default subscriber.
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for Messages {
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            struct Message(String);
+
+            impl Visit for Message {
+                fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                    if field.name() == "message" {
+                        let _ = write!(self.0, "{:?}", value);
+                    }
+                }
+            }
+
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            self.0.lock().unwrap().push(message.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_catch_panic() {
+        async fn panics() -> &'static str {
+            panic!("handler bug")
+        }
+        let messages = Messages::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(messages.clone()));
+        capture_panic_traces();
+        // panics outside of handlers go to the previous hook only
+        let _ = std::panic::catch_unwind(|| panic!("not a handler"));
+        assert!(PANIC_TRACE.with(|last| last.borrow().is_none()));
+        let routes = Router::new().route("/panic", get(panics));
+        let gateway = ApiGateway::new(0, "/api");
+        let app = gateway.router(routes).unwrap();
+        let (status, body) = send_json(app, test_util::get("/api/panic")).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["status"], "ERROR");
+        assert_eq!(body["error_code"], "INTERNAL_ERROR");
+        assert!(body["request_id"].is_string());
+
+        // the log points at the panic, not at the layer which caught it
+        let messages = messages.0.lock().unwrap();
+        let logged = messages
+            .iter()
+            .find(|message| message.starts_with("handler panicked: handler bug"))
+            .unwrap();
+        assert!(logged.contains(&format!("at {}:", file!())));
+
+        let metrics = gateway.metrics().render().unwrap();
+        assert!(metrics.contains("http_panics_total 1"));
+        assert!(metrics
+            .contains(r#"http_requests_total{method="GET",route="/api/panic",status="500"} 1"#));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = GatewayConfig {
+            timeout_secs: 0,
+            ..Default::default()
+        };
+        let res = ApiGateway::with_config(config, "/api").router(Router::new());
+        assert!(matches!(res, Err(AxError::ApplicationStartup(_))));
+    }
+
+    async fn h2c_request(http: HttpSettings) -> Result<Version, hyper::Error> {
+        let routes = Router::new().route("/version", get(|| async { "ok" }));
+        let gateway = ApiGateway::new(0, "/api").http_settings(http);
+        let app = gateway.router(routes).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = Handle::new();
+        let server = handle.clone();
+        tokio::spawn(async move { gateway.serve_listener(listener, app, server).await });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let res = async {
+            let (mut sender, conn) = hyper::client::conn::Builder::new()
+                .http2_only(true)
+                .handshake(stream)
+                .await?;
+            tokio::spawn(conn);
+            let res = sender
+                .send_request(
+                    Request::get(format!("http://{}/api/version", addr))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await?;
+            Ok(res.version())
+        }
+        .await;
+        handle.shutdown();
+        res
+    }
+
+    #[tokio::test]
+    async fn test_h2c() {
+        let version = h2c_request(HttpSettings {
+            http2_max_concurrent_streams: Some(10),
+            http2_keep_alive_interval_secs: Some(5),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(version, Version::HTTP_2);
+
+        let res = h2c_request(HttpSettings {
+            http2: false,
+            ..Default::default()
+        })
+        .await;
+        assert!(res.is_err());
+    }
 }
@@ -0,0 +1,252 @@
+use axum::{
+    body::HttpBody,
+    extract::MatchedPath,
+    http::{header::CONTENT_TYPE, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    BoxError, Extension, Router,
+};
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, Encoder, HistogramVec, IntCounter, IntCounterVec,
+    IntGaugeVec, Registry, TextEncoder,
+};
+use std::time::Instant;
+
+use crate::errors::AxError;
+
+/// Route label of requests no route matched, keeps the label set bounded.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// HTTP metrics of the gateway, in their own registry so services can add theirs.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    in_flight: IntGaugeVec,
+    response_size: HistogramVec,
+    rejections: IntCounterVec,
+    panics: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            opts!("http_requests_total", "Number of HTTP requests handled."),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let latency = HistogramVec::new(
+            histogram_opts!(
+                "http_request_duration_seconds",
+                "Time until the response headers were sent."
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let in_flight = IntGaugeVec::new(
+            opts!(
+                "http_requests_in_flight",
+                "Number of HTTP requests being handled."
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let response_size = HistogramVec::new(
+            histogram_opts!(
+                "http_response_size_bytes",
+                "Size of response bodies with a known length, before compression.",
+                exponential_buckets(64.0, 4.0, 10).unwrap()
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let rejections = IntCounterVec::new(
+            opts!(
+                "http_rejections_total",
+                "Requests rejected by the gateway middleware before reaching a handler."
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let panics = IntCounter::new(
+            "http_panics_total",
+            "Handler panics turned into 500 responses.",
+        )
+        .unwrap();
+        // names are unique, registering can not fail
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(in_flight.clone())).unwrap();
+        registry.register(Box::new(response_size.clone())).unwrap();
+        registry.register(Box::new(rejections.clone())).unwrap();
+        registry.register(Box::new(panics.clone())).unwrap();
+        Self {
+            registry,
+            requests,
+            latency,
+            in_flight,
+            response_size,
+            rejections,
+            panics,
+        }
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Counts an error turned into a response by [`crate::handle_error`].
+    pub(crate) fn record_rejection(&self, error: &BoxError) {
+        let reason = if error.is::<tower::timeout::error::Elapsed>() {
+            "timeout"
+        } else if error.is::<tower::load_shed::error::Overloaded>() {
+            "overloaded"
+        } else {
+            "other"
+        };
+        self.rejections.with_label_values(&[reason]).inc();
+    }
+
+    pub(crate) fn record_panic(&self) {
+        self.panics.inc();
+    }
+
+    /// The registry in Prometheus text format.
+    pub fn render(&self) -> Result<String, AxError> {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|err| AxError::InternalServerErrorWithContext(err.to_string()))?;
+        String::from_utf8(buffer)
+            .map_err(|err| AxError::InternalServerErrorWithContext(err.to_string()))
+    }
+
+    pub(crate) fn routes(&self, path: &str) -> Router {
+        Router::new()
+            .route(path, get(render))
+            .layer(Extension(self.clone()))
+    }
+}
+
+async fn render(Extension(metrics): Extension<Metrics>) -> Result<impl IntoResponse, AxError> {
+    Ok((
+        [(CONTENT_TYPE, TextEncoder::new().format_type().to_owned())],
+        metrics.render()?,
+    ))
+}
+
+/// Decrements the in-flight gauge also when the request future is dropped.
+struct InFlight<'a>(&'a IntGaugeVec, [&'a str; 2]);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.with_label_values(&self.1).dec();
+    }
+}
+
+/// Records the request metrics, labelled by the route template rather than the path.
+pub(crate) async fn track<B>(req: Request<B>, next: Next<B>, metrics: Metrics) -> Response {
+    let method = req.method().as_str().to_owned();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_owned());
+
+    let labels = [method.as_str(), route.as_str()];
+    metrics.in_flight.with_label_values(&labels).inc();
+    let _in_flight = InFlight(&metrics.in_flight, labels);
+    let start = Instant::now();
+    let res = next.run(req).await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let status = res.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    metrics.requests.with_label_values(&labels).inc();
+    metrics.latency.with_label_values(&labels).observe(elapsed);
+    if let Some(size) = res.body().size_hint().exact() {
+        metrics
+            .response_size
+            .with_label_values(&labels)
+            .observe(size as f64);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{http::StatusCode, routing::get, Router};
+
+    use crate::{
+        config::GatewayConfig,
+        test_util::{self, send, send_text},
+        ApiGateway,
+    };
+
+    async fn metrics(app: &Router) -> String {
+        let (status, body) = send_text(app.clone(), test_util::get("/metrics")).await;
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    #[tokio::test]
+    async fn test_request_metrics() {
+        let config = GatewayConfig {
+            metrics_path: Some("/metrics".to_owned()),
+            ..Default::default()
+        };
+        let routes = Router::new().route("/users/:id", get(|| async { "user" }));
+        let app = ApiGateway::with_config(config, "/api")
+            .router(routes)
+            .unwrap();
+        for path in ["/api/users/1", "/api/users/2", "/nope"] {
+            send(app.clone(), test_util::get(path)).await;
+        }
+
+        let text = metrics(&app).await;
+        assert!(text.contains(
+            r#"http_requests_total{method="GET",route="/api/users/:id",status="200"} 2"#
+        ));
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#)
+        );
+        assert!(text.contains(
+            r#"http_response_size_bytes_sum{method="GET",route="/api/users/:id",status="200"} 8"#
+        ));
+        assert!(text.contains(r#"http_requests_in_flight{method="GET",route="/api/users/:id"} 0"#));
+    }
+
+    #[tokio::test]
+    async fn test_rejections_are_counted() {
+        let config = GatewayConfig {
+            timeout_secs: 1,
+            metrics_path: Some("/internal/metrics".to_owned()),
+            ..Default::default()
+        };
+        let routes = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }),
+        );
+        let app = ApiGateway::with_config(config, "/api")
+            .router(routes)
+            .unwrap();
+        let res = send(app.clone(), test_util::get("/api/slow")).await;
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let (_, text) = send_text(app, test_util::get("/internal/metrics")).await;
+        assert!(text.contains(r#"http_rejections_total{reason="timeout"} 1"#));
+    }
+}
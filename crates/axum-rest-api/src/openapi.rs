@@ -0,0 +1,442 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use axum::{
+    http::header::CONTENT_TYPE,
+    response::{Html, IntoResponse},
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use utoipa::openapi::{
+    path::PathItem,
+    schema::{ArrayBuilder, ObjectBuilder, SchemaType},
+    Components, Content, OpenApi, Ref, RefOr, Response, Schema,
+};
+
+use crate::{errors::AxError, problem::PROBLEM_JSON};
+
+/// Documentation page served next to the OpenAPI document, its assets are set by
+/// [`DocsAssets`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DocsUi {
+    #[default]
+    None,
+    Swagger,
+    Redoc,
+}
+
+impl FromStr for DocsUi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "" => Ok(DocsUi::None),
+            "swagger" => Ok(DocsUi::Swagger),
+            "redoc" => Ok(DocsUi::Redoc),
+            _ => Err(format!("unknown docs ui {}", s)),
+        }
+    }
+}
+
+/// A script or stylesheet of the documentation page, pinned by its subresource
+/// integrity hash.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DocsAsset {
+    /// URL of an exact version, e.g.
+    /// `https://unpkg.com/swagger-ui-dist@5.9.0/swagger-ui-bundle.js`, or a path the
+    /// service serves a vendored copy on.
+    pub url: String,
+    /// `sha256-`, `sha384-` or `sha512-` followed by the base64 digest of the asset.
+    pub integrity: String,
+}
+
+impl DocsAsset {
+    pub fn new(url: &str, integrity: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            integrity: integrity.to_owned(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), AxError> {
+        let attribute = |value: &str| {
+            !value.is_empty()
+                && !value
+                    .chars()
+                    .any(|c| c.is_whitespace() || matches!(c, '"' | '<' | '>' | '&'))
+        };
+        let pinned = ["sha256-", "sha384-", "sha512-"]
+            .iter()
+            .any(|algorithm| self.integrity.starts_with(algorithm));
+        if !attribute(&self.url) || !attribute(&self.integrity) || !pinned {
+            return Err(AxError::ApplicationStartup(format!(
+                "invalid documentation asset {} with integrity {}",
+                self.url, self.integrity
+            )));
+        }
+        Ok(())
+    }
+
+    fn script(&self) -> String {
+        format!(
+            r#"<script src="{}" integrity="{}" crossorigin="anonymous"></script>"#,
+            self.url, self.integrity
+        )
+    }
+
+    fn stylesheet(&self) -> String {
+        format!(
+            r#"<link rel="stylesheet" href="{}" integrity="{}" crossorigin="anonymous"/>"#,
+            self.url, self.integrity
+        )
+    }
+}
+
+/// Assets of the [`DocsUi`] page. Nothing is loaded from elsewhere unless they are set:
+/// the page needs the script of the UI, Swagger UI also its stylesheet.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct DocsAssets {
+    pub script: Option<DocsAsset>,
+    pub stylesheet: Option<DocsAsset>,
+}
+
+impl DocsAssets {
+    pub(crate) fn validate(&self, ui: DocsUi) -> Result<(), AxError> {
+        let required = match ui {
+            DocsUi::None => return Ok(()),
+            DocsUi::Swagger => vec![("script", &self.script), ("stylesheet", &self.stylesheet)],
+            DocsUi::Redoc => vec![("script", &self.script)],
+        };
+        for (name, asset) in required {
+            match asset {
+                Some(asset) => asset.validate()?,
+                None => {
+                    return Err(AxError::ApplicationStartup(format!(
+                        "docs_ui {:?} requires the pinned {} of docs_assets",
+                        ui, name
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Responses any operation may get from [`AxError`] or the gateway middleware.
+const ERROR_RESPONSES: &[(&str, &str, &str)] = &[
+    ("400", "BadRequest", "The request is invalid."),
+    ("401", "Unauthorized", "Authentication is required."),
+    ("403", "Forbidden", "The user lacks the privilege."),
+    ("404", "NotFound", "The resource does not exist."),
+    (
+        "405",
+        "MethodNotAllowed",
+        "The method is not allowed on the resource.",
+    ),
+    ("408", "RequestTimeout", "The request took too long."),
+    (
+        "409",
+        "Conflict",
+        "The resource conflicts with the request.",
+    ),
+    ("413", "PayloadTooLarge", "The request body is too large."),
+    (
+        "415",
+        "UnsupportedMediaType",
+        "The request body has an unsupported type.",
+    ),
+    ("429", "TooManyRequests", "The rate limit is exceeded."),
+    ("500", "InternalServerError", "The server failed."),
+    ("503", "ServiceUnavailable", "The server is overloaded."),
+];
+
+/// Prepares a document of the routes passed to [`crate::ApiGateway::router`]: paths
+/// move under `root_path`, JSON bodies of successful responses become the `result`
+/// of the [`crate::errors::AxResponse`] envelope and every operation documents the
+/// error responses.
+pub(crate) fn document(mut doc: OpenApi, root_path: &str) -> OpenApi {
+    let root_path = root_path.trim_end_matches('/');
+    let paths = std::mem::take(&mut doc.paths.paths);
+    for (path, mut item) in paths {
+        wrap_operations(&mut item);
+        doc.paths
+            .paths
+            .insert(format!("{}{}", root_path, path), item);
+    }
+
+    let components = doc.components.get_or_insert_with(Components::new);
+    components.schemas.extend(envelope_schemas());
+    for (_, name, description) in ERROR_RESPONSES {
+        let mut response = Response::new(*description);
+        response.content.insert(
+            "application/json".to_owned(),
+            Content::new(Ref::from_schema_name("ErrorResponse")),
+        );
+        response.content.insert(
+            PROBLEM_JSON.to_owned(),
+            Content::new(Ref::from_schema_name("ProblemDetails")),
+        );
+        components
+            .responses
+            .insert(name.to_string(), RefOr::T(response));
+    }
+    doc
+}
+
+fn wrap_operations(item: &mut PathItem) {
+    for operation in item.operations.values_mut() {
+        for (status, response) in operation.responses.responses.iter_mut() {
+            let RefOr::T(response) = response else {
+                continue;
+            };
+            if !status.starts_with('2') {
+                continue;
+            }
+            if let Some(content) = response.content.get_mut("application/json") {
+                let result = std::mem::replace(&mut content.schema, RefOr::Ref(Ref::new("")));
+                content.schema = envelope(result);
+            }
+        }
+        for (status, name, _) in ERROR_RESPONSES {
+            operation
+                .responses
+                .responses
+                .entry(status.to_string())
+                .or_insert_with(|| {
+                    RefOr::Ref(Ref::new(format!("#/components/responses/{}", name)))
+                });
+        }
+    }
+}
+
+fn envelope(result: RefOr<Schema>) -> RefOr<Schema> {
+    ObjectBuilder::new()
+        .property("status", Ref::from_schema_name("ResponseStatus"))
+        .required("status")
+        .property("result", result)
+        .into()
+}
+
+fn string() -> ObjectBuilder {
+    ObjectBuilder::new().schema_type(SchemaType::String)
+}
+
+fn envelope_schemas() -> BTreeMap<String, RefOr<Schema>> {
+    let status = string().enum_values(Some(["OK", "ERROR"]));
+    let field_error = ObjectBuilder::new()
+        .property("field", string())
+        .property("code", string())
+        .property("message", string())
+        .required("field")
+        .required("code")
+        .required("message");
+    let error = ObjectBuilder::new()
+        .property("status", Ref::from_schema_name("ResponseStatus"))
+        .property("error_message", string())
+        .property(
+            "error_code",
+            string().description(Some("Stable code of the error, e.g. RESOURCE_NOT_FOUND.")),
+        )
+        .property("request_id", string())
+        .property(
+            "errors",
+            ArrayBuilder::new().items(Ref::from_schema_name("FieldError")),
+        )
+        .required("status")
+        .required("error_message");
+    let problem = ObjectBuilder::new()
+        .property("type", string())
+        .property("title", string())
+        .property(
+            "status",
+            ObjectBuilder::new().schema_type(SchemaType::Integer),
+        )
+        .property("detail", string())
+        .property("instance", string())
+        .property("code", string())
+        .property("request_id", string())
+        .required("type")
+        .required("title")
+        .required("status");
+    BTreeMap::from([
+        ("ResponseStatus".to_owned(), status.into()),
+        ("FieldError".to_owned(), field_error.into()),
+        ("ErrorResponse".to_owned(), error.into()),
+        ("ProblemDetails".to_owned(), problem.into()),
+    ])
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8"/>
+  <title>API documentation</title>
+  {stylesheet}
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  {script}
+  <script>SwaggerUIBundle({ url: "{spec_url}", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+const REDOC: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8"/>
+  <title>API documentation</title>
+</head>
+<body>
+  <redoc spec-url="{spec_url}"></redoc>
+  {script}
+</body>
+</html>
+"#;
+
+/// Serves `doc` as JSON on `spec_path` and the documentation page on `docs_path`.
+pub(crate) fn routes(
+    doc: &OpenApi,
+    spec_path: &str,
+    ui: DocsUi,
+    assets: &DocsAssets,
+    docs_path: &str,
+) -> Result<Router, AxError> {
+    let spec = serde_json::to_string(doc).map_err(|err| {
+        AxError::ApplicationStartup(format!("failed to serialize the OpenAPI document: {}", err))
+    })?;
+    let router = Router::new().route(
+        spec_path,
+        get(move || async move { ([(CONTENT_TYPE, "application/json")], spec).into_response() }),
+    );
+    let template = match ui {
+        DocsUi::None => return Ok(router),
+        DocsUi::Swagger => SWAGGER_UI,
+        DocsUi::Redoc => REDOC,
+    };
+    assets.validate(ui)?;
+    let asset = |asset: &Option<DocsAsset>, tag: fn(&DocsAsset) -> String| {
+        asset.as_ref().map(tag).unwrap_or_default()
+    };
+    let page = template
+        .replace(
+            "{stylesheet}",
+            &asset(&assets.stylesheet, DocsAsset::stylesheet),
+        )
+        .replace("{script}", &asset(&assets.script, DocsAsset::script))
+        .replace("{spec_url}", spec_path);
+    Ok(router.route(docs_path, get(move || async move { Html(page) })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get, Router};
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use utoipa::{OpenApi, ToSchema};
+
+    use super::{DocsAsset, DocsAssets, DocsUi};
+    use crate::{
+        config::GatewayConfig,
+        errors::AxResponse,
+        test_util::{self, send},
+        ApiGateway,
+    };
+
+    #[derive(Serialize, ToSchema)]
+    struct Item {
+        id: u64,
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/items/{id}",
+        params(("id" = u64, Path, description = "Item id")),
+        responses(
+            (status = 200, description = "The item", body = Item),
+            (status = 404, description = "No such item")
+        )
+    )]
+    #[allow(dead_code)]
+    async fn get_item() -> AxResponse<Item> {
+        AxResponse::new(Item { id: 7 })
+    }
+
+    #[derive(OpenApi)]
+    #[openapi(paths(get_item), components(schemas(Item)))]
+    struct ApiDoc;
+
+    async fn get_ok(app: Router, uri: &str) -> (String, String) {
+        let res = send(app, test_util::get(uri)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let content_type = res.headers()["content-type"].to_str().unwrap().to_owned();
+        (
+            content_type,
+            String::from_utf8(res.body().to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_openapi() {
+        let config = GatewayConfig {
+            docs_ui: DocsUi::Redoc,
+            docs_assets: DocsAssets {
+                script: Some(DocsAsset::new(
+                    "https://cdn.redoc.ly/redoc/v2.1.3/bundles/redoc.standalone.js",
+                    "sha384-redoc",
+                )),
+                stylesheet: None,
+            },
+            ..Default::default()
+        };
+        let app = ApiGateway::with_config(config, "/api")
+            .openapi(ApiDoc::openapi())
+            .router(Router::new())
+            .unwrap();
+
+        let (content_type, spec) = get_ok(app.clone(), "/openapi.json").await;
+        assert_eq!(content_type, "application/json");
+        let spec: Value = serde_json::from_str(&spec).unwrap();
+        let responses = &spec["paths"]["/api/items/{id}"]["get"]["responses"];
+        assert_eq!(
+            responses["200"]["content"]["application/json"]["schema"],
+            json!({
+                "type": "object",
+                "required": ["status"],
+                "properties": {
+                    "status": { "$ref": "#/components/schemas/ResponseStatus" },
+                    "result": { "$ref": "#/components/schemas/Item" }
+                }
+            })
+        );
+        assert_eq!(responses["404"]["description"], "No such item");
+        for (status, name) in [
+            ("405", "MethodNotAllowed"),
+            ("409", "Conflict"),
+            ("415", "UnsupportedMediaType"),
+            ("429", "TooManyRequests"),
+        ] {
+            assert_eq!(
+                responses[status]["$ref"],
+                format!("#/components/responses/{}", name)
+            );
+        }
+        assert!(spec["components"]["schemas"]["ErrorResponse"].is_object());
+
+        let (content_type, page) = get_ok(app, "/docs").await;
+        assert!(content_type.starts_with("text/html"));
+        assert!(page.contains(r#"<redoc spec-url="/openapi.json">"#));
+        assert!(page.contains(r#"integrity="sha384-redoc" crossorigin="anonymous""#));
+    }
+
+    #[tokio::test]
+    async fn test_openapi_not_configured() {
+        let app = ApiGateway::new(0, "/api")
+            .router(Router::new().route("/", get(|| async { "ok" })))
+            .unwrap();
+        let res = send(app, test_util::get("/openapi.json")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,324 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, OriginalUri, RequestParts},
+    http::Uri,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::AxError, extract::Query};
+
+/// Page sizes accepted by [`Pagination`], set for a group of routes with
+/// `.layer(Extension(PaginationConfig::new(50, 500)))`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaginationConfig {
+    /// Page size when the request does not ask for one.
+    pub default_limit: u32,
+    pub max_limit: u32,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: 20,
+            max_limit: 100,
+        }
+    }
+}
+
+impl PaginationConfig {
+    pub fn new(default_limit: u32, max_limit: u32) -> Self {
+        Self {
+            default_limit,
+            max_limit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageMode {
+    /// `?page=2&per_page=20`, pages start at 1.
+    Offset { page: u32, per_page: u32 },
+    /// `?cursor=abc&limit=20`, the first page has no cursor.
+    Cursor { cursor: Option<String>, limit: u32 },
+}
+
+#[derive(Debug, Deserialize)]
+struct PageParams {
+    page: Option<u32>,
+    per_page: Option<u32>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+/// Page requested by the query string, either `page`/`per_page` or `cursor`/`limit`.
+/// Without parameters the first page of the default size is requested.
+///
+/// ```ignore
+/// async fn list(pagination: Pagination) -> AxResult<Page<Item>> {
+///     let (items, total) = repo.list(pagination.offset(), pagination.limit()).await?;
+///     Ok(AxResponse::new(pagination.page(items, total)))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pagination {
+    pub mode: PageMode,
+    uri: Uri,
+}
+
+impl Pagination {
+    pub fn limit(&self) -> u32 {
+        match &self.mode {
+            PageMode::Offset { per_page, .. } => *per_page,
+            PageMode::Cursor { limit, .. } => *limit,
+        }
+    }
+
+    /// Items to skip, always 0 for cursor pagination.
+    pub fn offset(&self) -> u64 {
+        match &self.mode {
+            PageMode::Offset { page, per_page } => (*page as u64 - 1) * *per_page as u64,
+            PageMode::Cursor { .. } => 0,
+        }
+    }
+
+    pub fn cursor(&self) -> Option<&str> {
+        match &self.mode {
+            PageMode::Cursor { cursor, .. } => cursor.as_deref(),
+            PageMode::Offset { .. } => None,
+        }
+    }
+
+    /// Page of an offset paginated listing of `total` items.
+    pub fn page<T>(&self, items: Vec<T>, total: u64) -> Page<T> {
+        let (page, per_page) = match &self.mode {
+            PageMode::Offset { page, per_page } => (*page as u64, *per_page as u64),
+            PageMode::Cursor { limit, .. } => (1, *limit as u64),
+        };
+        let last = total.div_ceil(per_page).max(1);
+        let link = |page: u64| {
+            self.link(&[
+                ("page", page.to_string()),
+                ("per_page", per_page.to_string()),
+            ])
+        };
+        Page {
+            items,
+            total: Some(total),
+            next_cursor: None,
+            links: PageLinks {
+                self_link: link(page),
+                first: Some(link(1)),
+                prev: (page > 1).then(|| link((page - 1).min(last))),
+                next: (page < last).then(|| link(page + 1)),
+                last: Some(link(last)),
+            },
+        }
+    }
+
+    /// Page of a cursor paginated listing, `next_cursor` is `None` on the last page.
+    pub fn cursor_page<T>(&self, items: Vec<T>, next_cursor: Option<String>) -> Page<T> {
+        let limit = self.limit().to_string();
+        let self_link = match self.cursor() {
+            Some(cursor) => self.link(&[("cursor", cursor.to_owned()), ("limit", limit.clone())]),
+            None => self.link(&[("limit", limit.clone())]),
+        };
+        Page {
+            items,
+            total: None,
+            links: PageLinks {
+                self_link,
+                first: Some(self.link(&[("limit", limit.clone())])),
+                prev: None,
+                next: next_cursor
+                    .as_ref()
+                    .map(|cursor| self.link(&[("cursor", cursor.clone()), ("limit", limit)])),
+                last: None,
+            },
+            next_cursor,
+        }
+    }
+
+    /// The request URI with its pagination parameters replaced by `params`.
+    fn link(&self, params: &[(&str, String)]) -> String {
+        let query = self.uri.query().unwrap_or_default();
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            if !matches!(name.as_ref(), "page" | "per_page" | "cursor" | "limit") {
+                serializer.append_pair(&name, &value);
+            }
+        }
+        for (name, value) in params {
+            serializer.append_pair(name, value);
+        }
+        format!("{}?{}", self.uri.path(), serializer.finish())
+    }
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Pagination {
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let config = req
+            .extensions()
+            .get::<PaginationConfig>()
+            .copied()
+            .unwrap_or_default();
+        let Query(params) = Query::<PageParams>::from_request(req).await?;
+        let check_limit = |name: &str, limit: Option<u32>| match limit {
+            None => Ok(config.default_limit),
+            Some(limit) if (1..=config.max_limit).contains(&limit) => Ok(limit),
+            Some(_) => Err(AxError::BadRequest(format!(
+                "{} must be between 1 and {}",
+                name, config.max_limit
+            ))),
+        };
+        let mode = match params {
+            PageParams {
+                page: None,
+                per_page: None,
+                cursor,
+                limit,
+            } if cursor.is_some() || limit.is_some() => PageMode::Cursor {
+                cursor,
+                limit: check_limit("limit", limit)?,
+            },
+            PageParams {
+                page,
+                per_page,
+                cursor: None,
+                limit: None,
+            } => PageMode::Offset {
+                page: match page {
+                    Some(0) => return Err(AxError::BadRequest("page starts at 1".to_owned())),
+                    page => page.unwrap_or(1),
+                },
+                per_page: check_limit("per_page", per_page)?,
+            },
+            _ => {
+                return Err(AxError::BadRequest(
+                    "use either page and per_page or cursor and limit".to_owned(),
+                ))
+            }
+        };
+        let uri = match req.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.clone(),
+            None => req.uri().clone(),
+        };
+        Ok(Self { mode, uri })
+    }
+}
+
+/// Links of a [`Page`], relative to the host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageLinks {
+    #[serde(rename = "self")]
+    pub self_link: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<String>,
+}
+
+/// One page of a listing, the `result` of an [`crate::errors::AxResponse`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of items of all pages, unknown with cursor pagination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    pub links: PageLinks,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get, Extension, Router};
+    use serde_json::{json, Value};
+
+    use super::{Page, PageMode, Pagination, PaginationConfig};
+    use crate::{
+        errors::AxResponse,
+        test_util::{self, send_json},
+        ApiGateway,
+    };
+
+    async fn list(pagination: Pagination) -> AxResponse<Page<u64>> {
+        let total = 45;
+        let items = (pagination.offset()..total)
+            .take(pagination.limit() as usize)
+            .collect();
+        match pagination.mode {
+            PageMode::Offset { .. } => AxResponse::new(pagination.page(items, total)),
+            PageMode::Cursor { .. } => {
+                AxResponse::new(pagination.cursor_page(items, Some("c2".to_owned())))
+            }
+        }
+    }
+
+    async fn get_page(uri: &str) -> (StatusCode, Value) {
+        let routes = Router::new()
+            .route("/items", get(list))
+            .layer(Extension(PaginationConfig::new(10, 20)));
+        let app = ApiGateway::new(0, "/api").router(routes).unwrap();
+        send_json(app, test_util::get(uri)).await
+    }
+
+    #[tokio::test]
+    async fn test_offset_pagination() {
+        let (status, body) = get_page("/api/items?q=x&page=2").await;
+        assert_eq!(status, StatusCode::OK);
+        let page = &body["result"];
+        assert_eq!(page["items"][0], 10);
+        assert_eq!(page["total"], 45);
+        assert_eq!(
+            page["links"],
+            json!({
+                "self": "/api/items?q=x&page=2&per_page=10",
+                "first": "/api/items?q=x&page=1&per_page=10",
+                "prev": "/api/items?q=x&page=1&per_page=10",
+                "next": "/api/items?q=x&page=3&per_page=10",
+                "last": "/api/items?q=x&page=5&per_page=10"
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pagination() {
+        let (status, body) = get_page("/api/items?limit=5").await;
+        assert_eq!(status, StatusCode::OK);
+        let page = &body["result"];
+        assert_eq!(page["items"].as_array().unwrap().len(), 5);
+        assert_eq!(page["next_cursor"], "c2");
+        assert_eq!(page["links"]["next"], "/api/items?cursor=c2&limit=5");
+        assert!(page.get("total").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_pagination() {
+        for (uri, message) in [
+            (
+                "/api/items?per_page=50",
+                "per_page must be between 1 and 20",
+            ),
+            ("/api/items?page=0", "page starts at 1"),
+            (
+                "/api/items?page=2&cursor=abc",
+                "use either page and per_page or cursor and limit",
+            ),
+            (
+                "/api/items?page=two",
+                "invalid query parameter `page`: invalid digit found in string",
+            ),
+        ] {
+            let (status, body) = get_page(uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["error_message"], message);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::str::FromStr;
+
+use axum::{
+    http::{header::ACCEPT, header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::context::RequestContext;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Members of the problem details, which extensions cannot be named as.
+const STANDARD_MEMBERS: [&str; 5] = ["type", "title", "status", "detail", "instance"];
+
+/// How [`crate::errors::AxError`] is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    /// The `{status, result, error_message}` [`crate::errors::AxResponse`] envelope.
+    #[default]
+    Envelope,
+    /// RFC 7807 `application/problem+json`.
+    Problem,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "envelope" => Ok(ErrorFormat::Envelope),
+            "problem" => Ok(ErrorFormat::Problem),
+            _ => Err(format!("unknown error format {}", s)),
+        }
+    }
+}
+
+impl ErrorFormat {
+    /// The gateway format, unless the client asks for problem details in `Accept` with a
+    /// weight other than `q=0`.
+    pub(crate) fn negotiate(self, headers: &HeaderMap) -> Self {
+        let accepts_problem = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media| {
+                let mut params = media.split(';');
+                params.next().unwrap_or_default().trim() == PROBLEM_JSON
+                    && !params.any(|param| {
+                        param
+                            .trim()
+                            .strip_prefix("q=")
+                            .and_then(|q| q.trim().parse::<f32>().ok())
+                            .is_some_and(|q| q == 0.0)
+                    })
+            });
+        if accepts_problem {
+            ErrorFormat::Problem
+        } else {
+            self
+        }
+    }
+
+    /// Format of the request being handled, the envelope outside of a request.
+    pub fn current() -> Self {
+        RequestContext::current()
+            .map(|cx| cx.error_format)
+            .unwrap_or_default()
+    }
+}
+
+/// RFC 7807 problem details, rendered as `application/problem+json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Extension members, serialized next to the standard ones. Members named like a
+    /// standard one are left out of the response.
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+impl ProblemDetails {
+    /// A problem of type `about:blank`, titled by the status reason. Inside a request
+    /// the instance is the request path and the request id is added as `request_id`.
+    pub fn new(status: StatusCode) -> Self {
+        let mut problem = Self {
+            type_uri: "about:blank".to_owned(),
+            title: status.canonical_reason().unwrap_or_default().to_owned(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        };
+        if let Some(cx) = RequestContext::current() {
+            problem.instance = Some(cx.path);
+            problem = problem.extension("request_id", cx.request_id.to_string());
+        }
+        problem
+    }
+
+    pub fn type_uri(mut self, type_uri: &str) -> Self {
+        self.type_uri = type_uri.to_owned();
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_owned();
+        self
+    }
+
+    pub fn detail<S: Into<String>>(mut self, detail: S) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn instance(mut self, instance: &str) -> Self {
+        self.instance = Some(instance.to_owned());
+        self
+    }
+
+    /// Adds an extension member, ignored when `name` is one of the standard members.
+    pub fn extension<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        if !STANDARD_MEMBERS.contains(&name) {
+            self.extensions.insert(name.to_owned(), value.into());
+        }
+        self
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(mut self) -> Response {
+        self.extensions
+            .retain(|name, _| !STANDARD_MEMBERS.contains(&name.as_str()));
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        match serde_json::to_vec(&self) {
+            Ok(body) => (
+                status,
+                [(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))],
+                body,
+            )
+                .into_response(),
+            Err(_) => status.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{ACCEPT, CONTENT_TYPE},
+            HeaderMap, Request, StatusCode,
+        },
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+    use serde_json::Value;
+
+    use super::{ErrorFormat, ProblemDetails, PROBLEM_JSON};
+    use crate::{
+        config::GatewayConfig,
+        errors::AxError,
+        test_util::{read, send},
+        ApiGateway,
+    };
+
+    #[test]
+    fn test_negotiate() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            ErrorFormat::Envelope.negotiate(&headers),
+            ErrorFormat::Envelope
+        );
+        headers.insert(
+            ACCEPT,
+            "application/json, application/problem+json;q=0.9"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            ErrorFormat::Envelope.negotiate(&headers),
+            ErrorFormat::Problem
+        );
+        for refused in [
+            "application/problem+json;q=0",
+            "application/json, application/problem+json; q=0.0",
+        ] {
+            headers.insert(ACCEPT, refused.parse().unwrap());
+            assert_eq!(
+                ErrorFormat::Envelope.negotiate(&headers),
+                ErrorFormat::Envelope
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reserved_extensions() {
+        let mut problem = ProblemDetails::new(StatusCode::CONFLICT)
+            .extension("status", 200)
+            .extension("type", "https://example.com/other")
+            .extension("order_id", 7);
+        assert_eq!(problem.extensions.len(), 1);
+        problem
+            .extensions
+            .insert("title".to_owned(), "Other".into());
+
+        let res = read(problem.into_response()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert_eq!(body.matches("\"title\"").count(), 1);
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["status"], 409);
+        assert_eq!(body["type"], "about:blank");
+        assert_eq!(body["title"], "Conflict");
+        assert_eq!(body["order_id"], 7);
+    }
+
+    async fn not_found(app: Router, accept: Option<&str>) -> (StatusCode, String, Value) {
+        let mut req = Request::get("/api/items/7");
+        if let Some(accept) = accept {
+            req = req.header(ACCEPT, accept);
+        }
+        let res = send(app, req.body(Body::empty()).unwrap()).await;
+        let content_type = res.headers()[CONTENT_TYPE].to_str().unwrap().to_owned();
+        let body = serde_json::from_slice(res.body()).unwrap();
+        (res.status(), content_type, body)
+    }
+
+    fn routes() -> Router {
+        Router::new().route(
+            "/items/:id",
+            get(|| async { Err::<(), _>(AxError::NotFound("item 7 does not exist".to_owned())) }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_error_format() {
+        let app = ApiGateway::new(0, "/api").router(routes()).unwrap();
+        let (status, content_type, body) = not_found(app.clone(), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body["error_message"], "item 7 does not exist");
+
+        let (_, content_type, body) = not_found(app, Some(PROBLEM_JSON)).await;
+        assert_eq!(content_type, PROBLEM_JSON);
+        assert_eq!(body["type"], "about:blank");
+        assert_eq!(body["title"], "Not Found");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["detail"], "item 7 does not exist");
+        assert_eq!(body["instance"], "/api/items/7");
+        assert!(body["request_id"].is_string());
+        assert_eq!(body["code"], "RESOURCE_NOT_FOUND");
+
+        let config = GatewayConfig {
+            error_format: ErrorFormat::Problem,
+            ..Default::default()
+        };
+        let app = ApiGateway::with_config(config, "/api")
+            .router(routes())
+            .unwrap();
+        let (status, content_type, _) = not_found(app, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, PROBLEM_JSON);
+    }
+}
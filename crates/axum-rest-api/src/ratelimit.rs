@@ -0,0 +1,492 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Once},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    extract::ConnectInfo,
+    http::Request,
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use keycloak::TokenClaim;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+use tracing::error;
+
+use crate::errors::AxError;
+
+/// Buckets kept in memory by default.
+const MAX_BUCKETS: usize = 10_000;
+
+/// How often buckets which refilled are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token bucket: `burst` requests at once, refilled at `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    burst: u32,
+    per_second: f64,
+}
+
+impl RateLimit {
+    pub fn per_second(requests: u32) -> Self {
+        Self {
+            burst: requests.max(1),
+            per_second: requests.max(1) as f64,
+        }
+    }
+
+    pub fn per_minute(requests: u32) -> Self {
+        Self {
+            burst: requests.max(1),
+            per_second: requests.max(1) as f64 / 60.0,
+        }
+    }
+
+    /// Requests allowed at once, defaults to the requests per period.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+/// What a request is counted against.
+#[derive(Debug, Clone)]
+pub enum RateLimitKey {
+    /// Peer address of the connection.
+    ClientIp,
+    /// `sub` of the token validated by [`crate::auth::require_auth`], which has to be
+    /// layered outside the limiter. Anonymous requests fall back to the client IP.
+    Subject,
+    /// [`ApiKeyId`] of the key validated by the service, which has to be layered outside
+    /// the limiter. Requests without it fall back to the client IP.
+    ApiKey,
+}
+
+/// Identity of a validated API key, inserted into the request extensions by the
+/// middleware checking the key. Only a hash of it is kept by the store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyId(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Allowed { remaining: u32 },
+    Limited { retry_after: Duration },
+}
+
+/// Where the buckets live, in memory by default; implement it to share limits
+/// between instances.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes one token from the bucket of `key`.
+    async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<Decision, AxError>;
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket is refilled, from then on it is the same as no bucket.
+    full_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.updated = now;
+    }
+}
+
+type Buckets = Mutex<HashMap<String, Bucket>>;
+
+/// Buckets of this process, limits are per instance.
+///
+/// At most `capacity` keys have a bucket. A new key coming while the store is full takes
+/// the place of the bucket closest to refilled, which loses the least of what the store
+/// counts. Buckets which refilled are swept every minute by a task started on first use.
+#[derive(Debug)]
+pub struct InMemoryStore {
+    buckets: Arc<Buckets>,
+    capacity: usize,
+    sweeper: Once,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::with_capacity(MAX_BUCKETS)
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+            capacity: capacity.max(1),
+            sweeper: Once::new(),
+        }
+    }
+
+    fn take(&self, key: &str, limit: &RateLimit, now: Instant) -> Decision {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if buckets.len() >= self.capacity && !buckets.contains_key(key) {
+            let fullest = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.full_at)
+                .map(|(key, _)| key.clone());
+            if let Some(fullest) = fullest {
+                buckets.remove(&fullest);
+            }
+        }
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+            full_at: now,
+        });
+        bucket.refill(limit, now);
+        let decision = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Decision::Allowed {
+                remaining: bucket.tokens as u32,
+            }
+        } else {
+            Decision::Limited {
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / limit.per_second),
+            }
+        };
+        let missing = limit.burst as f64 - bucket.tokens;
+        bucket.full_at = now + Duration::from_secs_f64(missing / limit.per_second);
+        decision
+    }
+
+    fn start_sweeper(&self) {
+        self.sweeper.call_once(|| {
+            // the task ends with the store
+            let buckets = Arc::downgrade(&self.buckets);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    match buckets.upgrade() {
+                        Some(buckets) => sweep(&buckets, Instant::now()),
+                        None => break,
+                    }
+                }
+            });
+        });
+    }
+}
+
+/// Drops the buckets which refilled.
+fn sweep(buckets: &Buckets, now: Instant) {
+    buckets
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .retain(|_, bucket| bucket.full_at > now);
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<Decision, AxError> {
+        self.start_sweeper();
+        Ok(self.take(key, limit, Instant::now()))
+    }
+}
+
+/// Rate limit of a route group, applied with `route_layer`.
+///
+/// ```ignore
+/// let login = RateLimiter::new("login", RateLimit::per_minute(10), RateLimitKey::ClientIp);
+/// let routes = Router::new()
+///     .route("/login", post(login_handler))
+///     .route_layer(login.layer());
+/// ```
+#[derive(Clone)]
+pub struct RateLimiter {
+    name: String,
+    limit: RateLimit,
+    key: RateLimitKey,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    /// `name` separates the buckets of route groups sharing a store.
+    pub fn new(name: &str, limit: RateLimit, key: RateLimitKey) -> Self {
+        Self {
+            name: name.to_owned(),
+            limit,
+            key,
+            store: Arc::new(InMemoryStore::new()),
+        }
+    }
+
+    pub fn with_store(mut self, store: Arc<dyn RateLimitStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    pub fn layer(&self) -> RateLimitLayer {
+        RateLimitLayer {
+            limiter: self.clone(),
+        }
+    }
+
+    fn key<B>(&self, req: &Request<B>) -> String {
+        let client_ip = || {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+                .unwrap_or_else(|| "unknown".to_owned())
+        };
+        let key = match &self.key {
+            RateLimitKey::ClientIp => format!("ip:{}", client_ip()),
+            RateLimitKey::Subject => match req.extensions().get::<TokenClaim>() {
+                Some(claims) if !claims.sub.is_empty() => format!("sub:{}", claims.sub),
+                _ => format!("ip:{}", client_ip()),
+            },
+            RateLimitKey::ApiKey => match req.extensions().get::<ApiKeyId>() {
+                Some(ApiKeyId(id)) => format!("key:{:x}", Sha256::digest(id.as_bytes())),
+                None => format!("ip:{}", client_ip()),
+            },
+        };
+        format!("{}:{}", self.name, key)
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: RateLimiter,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<Request<B>> for RateLimitService<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // the clone may not be ready, call the instance `poll_ready` was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            let key = limiter.key(&req);
+            match limiter.store.acquire(&key, &limiter.limit).await {
+                Ok(Decision::Limited { retry_after }) => {
+                    return Ok(AxError::TooManyRequests(retry_after).into_response())
+                }
+                Ok(Decision::Allowed { .. }) => {}
+                // fail open, an unavailable store must not take the service down
+                Err(err) => error!("rate limit store failed: {}", err),
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header::RETRY_AFTER, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use keycloak::TokenClaim;
+    use serde_json::Value;
+
+    use super::{sweep, ApiKeyId, Decision, InMemoryStore, RateLimit, RateLimitKey, RateLimiter};
+    use crate::test_util::send;
+
+    #[test]
+    fn test_token_bucket() {
+        let store = InMemoryStore::new();
+        let limit = RateLimit::per_second(2);
+        let now = Instant::now();
+        assert_eq!(
+            store.take("a", &limit, now),
+            Decision::Allowed { remaining: 1 }
+        );
+        assert_eq!(
+            store.take("a", &limit, now),
+            Decision::Allowed { remaining: 0 }
+        );
+        assert_eq!(
+            store.take("a", &limit, now),
+            Decision::Limited {
+                retry_after: Duration::from_millis(500)
+            }
+        );
+        // other keys have their own bucket
+        assert!(matches!(
+            store.take("b", &limit, now),
+            Decision::Allowed { .. }
+        ));
+        // one token is back after half a second
+        let later = now + Duration::from_millis(500);
+        assert!(matches!(
+            store.take("a", &limit, later),
+            Decision::Allowed { .. }
+        ));
+        assert!(matches!(
+            store.take("a", &limit, later),
+            Decision::Limited { .. }
+        ));
+    }
+
+    #[test]
+    fn test_store_capacity() {
+        let store = InMemoryStore::with_capacity(2);
+        let limit = RateLimit::per_minute(1);
+        let now = Instant::now();
+        // the store is filled by many addresses of one client
+        for (attacker, at) in [("attacker-1", 0), ("attacker-2", 10)] {
+            assert!(matches!(
+                store.take(attacker, &limit, now + Duration::from_secs(at)),
+                Decision::Allowed { .. }
+            ));
+        }
+        // a new key is still served, in place of the bucket closest to refilled
+        let later = now + Duration::from_secs(20);
+        assert!(matches!(
+            store.take("innocent", &limit, later),
+            Decision::Allowed { .. }
+        ));
+        {
+            let buckets = store.buckets.lock().unwrap();
+            assert_eq!(buckets.len(), 2);
+            assert!(buckets.contains_key("attacker-2") && buckets.contains_key("innocent"));
+        }
+        assert!(matches!(
+            store.take("attacker-2", &limit, later),
+            Decision::Limited { .. }
+        ));
+
+        sweep(&store.buckets, now + Duration::from_secs(75));
+        assert_eq!(store.buckets.lock().unwrap().len(), 1);
+        sweep(&store.buckets, now + Duration::from_secs(80));
+        assert!(store.buckets.lock().unwrap().is_empty());
+    }
+
+    fn request(ip: [u8; 4]) -> Request<Body> {
+        let mut req = Request::get("/limited").body(Body::empty()).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((ip, 4000))));
+        req
+    }
+
+    fn limited_app(limiter: RateLimiter) -> Router {
+        Router::new()
+            .route("/limited", get(|| async { "ok" }))
+            .route_layer(limiter.layer())
+    }
+
+    #[tokio::test]
+    async fn test_limit_client_ip() {
+        let limiter = RateLimiter::new("test", RateLimit::per_minute(1), RateLimitKey::ClientIp);
+        let app = limited_app(limiter);
+
+        let res = send(app.clone(), request([10, 0, 0, 1])).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = send(app.clone(), request([10, 0, 0, 1])).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = res.headers()[RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&retry_after));
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["status"], "ERROR");
+
+        let res = send(app, request([10, 0, 0, 2])).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_limit_subject_and_api_key() {
+        let app = limited_app(RateLimiter::new(
+            "test",
+            RateLimit::per_minute(1),
+            RateLimitKey::Subject,
+        ));
+        for (sub, status) in [
+            ("alice", StatusCode::OK),
+            ("bob", StatusCode::OK),
+            ("alice", StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let mut req = request([10, 0, 0, 1]);
+            req.extensions_mut().insert(TokenClaim {
+                sub: sub.to_owned(),
+                ..Default::default()
+            });
+            assert_eq!(send(app.clone(), req).await.status(), status);
+        }
+
+        let store = Arc::new(InMemoryStore::new());
+        let app = limited_app(
+            RateLimiter::new("test", RateLimit::per_minute(1), RateLimitKey::ApiKey)
+                .with_store(store.clone()),
+        );
+        for (key, status) in [
+            ("key-1", StatusCode::OK),
+            ("key-2", StatusCode::OK),
+            ("key-1", StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let mut req = request([10, 0, 0, 1]);
+            req.extensions_mut().insert(ApiKeyId(key.to_owned()));
+            assert_eq!(send(app.clone(), req).await.status(), status);
+        }
+        // requests without a validated key are counted against their address
+        let res = send(app, request([10, 0, 0, 1])).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let buckets = store.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), 3);
+        assert!(buckets.keys().all(|key| !key.contains("key-")));
+    }
+}
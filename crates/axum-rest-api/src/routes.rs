@@ -0,0 +1,359 @@
+use std::sync::Arc;
+
+use axum::{middleware, Router};
+use keycloak::KeycloakClient;
+use utoipa::openapi::{OpenApi, Tag};
+
+use crate::{
+    auth::{require_auth, require_roles},
+    errors::AxError,
+    state::{AppState, StateKey},
+};
+
+/// A feature of the service, e.g. orders, registered with [`crate::ApiGateway::module`]
+/// and served below the root path at its own prefix.
+///
+/// ```ignore
+/// struct Orders;
+///
+/// impl RouteModule for Orders {
+///     fn name(&self) -> &str { "orders" }
+///     fn path(&self) -> &str { "/orders" }
+///     fn router(&self) -> Router { Router::new().route("/:id", get(get_order)) }
+///     fn roles(&self) -> &[&str] { &["orders"] }
+///     fn tags(&self) -> &[&str] { &["Orders"] }
+///     fn openapi(&self) -> Option<OpenApi> { Some(OrdersDoc::openapi()) }
+/// }
+/// ```
+pub trait RouteModule: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    /// Prefix of the routes below the root path, e.g. `/orders`. Prefixes of modules
+    /// and API versions must not overlap, which the gateway checks when it is built.
+    /// The routes passed to [`crate::ApiGateway::router`] are not checked, a route of
+    /// them below the prefix makes axum panic like [`Router::merge`] does.
+    fn path(&self) -> &str;
+
+    /// Routes relative to [`RouteModule::path`].
+    fn router(&self) -> Router;
+
+    /// Realm roles the caller needs all of, the routes are public when empty. Checked
+    /// with [`require_auth`], which requires the `KeycloakClient` state.
+    fn roles(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Types of [`crate::state::State`] the routes use, checked when the gateway is built.
+    fn required_state(&self) -> Vec<StateKey> {
+        vec![]
+    }
+
+    /// Tags added to the operations of [`RouteModule::openapi`].
+    fn tags(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Document of the routes, relative to [`RouteModule::path`], merged into the one
+    /// of [`crate::ApiGateway::openapi`].
+    fn openapi(&self) -> Option<OpenApi> {
+        None
+    }
+}
+
+/// Merges the routers of the modules into `routes`, failing on invalid or
+/// overlapping prefixes and missing state.
+pub(crate) fn compose(
+    modules: &[Box<dyn RouteModule>],
+    state: &AppState,
+    mut routes: Router,
+) -> Result<Router, AxError> {
+    for (index, module) in modules.iter().enumerate() {
+        let path = module_path(module.as_ref())?;
+        if modules[..index]
+            .iter()
+            .any(|other| other.name() == module.name())
+        {
+            return Err(AxError::ApplicationStartup(format!(
+                "route module {} is registered twice",
+                module.name()
+            )));
+        }
+        check_prefix(
+            &modules[..index],
+            path,
+            &format!("route module {}", module.name()),
+        )?;
+
+        let required_by = format!("route module {}", module.name());
+        state.check(&module.required_state(), &required_by)?;
+        let mut router = module.router();
+        if !module.roles().is_empty() {
+            state.check(&[StateKey::of::<KeycloakClient>()], &required_by)?;
+            let roles: Arc<[String]> = module.roles().iter().map(|role| role.to_string()).collect();
+            router = router
+                .route_layer(middleware::from_fn(move |req, next| {
+                    require_roles(req, next, roles.clone())
+                }))
+                .route_layer(middleware::from_fn(require_auth));
+        }
+        routes = routes.merge(Router::new().nest(path, router));
+    }
+    Ok(routes)
+}
+
+/// Fails when `path`, the prefix of `what`, overlaps the prefix of a module.
+pub(crate) fn check_prefix(
+    modules: &[Box<dyn RouteModule>],
+    path: &str,
+    what: &str,
+) -> Result<(), AxError> {
+    for module in modules {
+        let module_path = module_path(module.as_ref())?;
+        if overlaps(path, module_path) {
+            return Err(AxError::ApplicationStartup(format!(
+                "{} at {} conflicts with route module {} at {}",
+                what,
+                path,
+                module.name(),
+                module_path
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn module_path(module: &dyn RouteModule) -> Result<&str, AxError> {
+    let path = module.path().trim_end_matches('/');
+    if !path.starts_with('/') || path.contains(['*', ':']) {
+        return Err(AxError::ApplicationStartup(format!(
+            "route module {} has invalid path {}",
+            module.name(),
+            module.path()
+        )));
+    }
+    Ok(path)
+}
+
+/// Whether one prefix is the other or one of its parents.
+fn overlaps(path: &str, other: &str) -> bool {
+    let (short, long) = if path.len() <= other.len() {
+        (path, other)
+    } else {
+        (other, path)
+    };
+    long.strip_prefix(short)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Document of a module with its paths moved under its prefix and its tags added.
+pub(crate) fn module_openapi(module: &dyn RouteModule) -> Option<OpenApi> {
+    let mut doc = module.openapi()?;
+    let prefix = module.path().trim_end_matches('/');
+    let tags: Vec<String> = module.tags().iter().map(|tag| tag.to_string()).collect();
+    let paths = std::mem::take(&mut doc.paths.paths);
+    for (path, mut item) in paths {
+        for operation in item.operations.values_mut() {
+            let operation_tags = operation.tags.get_or_insert_with(Vec::new);
+            for tag in &tags {
+                if !operation_tags.contains(tag) {
+                    operation_tags.push(tag.clone());
+                }
+            }
+        }
+        let path = match path.as_str() {
+            "/" => prefix.to_owned(),
+            path => format!("{}{}", prefix, path),
+        };
+        doc.paths.paths.insert(path, item);
+    }
+    if !tags.is_empty() {
+        let doc_tags = doc.tags.get_or_insert_with(Vec::new);
+        for tag in tags {
+            if !doc_tags.iter().any(|doc_tag| doc_tag.name == tag) {
+                doc_tags.push(Tag::new(tag));
+            }
+        }
+    }
+    Some(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use keycloak::{testing::TokenMinter, KeycloakClient};
+    use utoipa::OpenApi;
+
+    use super::RouteModule;
+    use crate::{
+        errors::AxError,
+        test_util::{self, send, send_json},
+        versioning::{ApiVersion, ApiVersions, VersionSelector},
+        ApiGateway,
+    };
+
+    #[utoipa::path(get, path = "/{id}", responses((status = 200, description = "The order")))]
+    #[allow(dead_code)]
+    async fn get_order() -> &'static str {
+        "order"
+    }
+
+    #[derive(OpenApi)]
+    #[openapi(paths(get_order))]
+    struct OrdersDoc;
+
+    #[derive(OpenApi)]
+    #[openapi()]
+    struct ApiDoc;
+
+    struct Module {
+        name: &'static str,
+        path: &'static str,
+        roles: &'static [&'static str],
+    }
+
+    impl RouteModule for Module {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn path(&self) -> &str {
+            self.path
+        }
+
+        fn router(&self) -> Router {
+            Router::new().route("/:id", get(get_order))
+        }
+
+        fn roles(&self) -> &[&str] {
+            self.roles
+        }
+
+        fn tags(&self) -> &[&str] {
+            &["Orders"]
+        }
+
+        fn openapi(&self) -> Option<utoipa::openapi::OpenApi> {
+            Some(OrdersDoc::openapi())
+        }
+    }
+
+    const ORDERS: Module = Module {
+        name: "orders",
+        path: "/orders",
+        roles: &["orders"],
+    };
+
+    const CATALOG: Module = Module {
+        name: "catalog",
+        path: "/catalog/",
+        roles: &[],
+    };
+
+    fn gateway() -> ApiGateway<'static> {
+        let keycloak: KeycloakClient = Arc::new(TokenMinter::new().keycloak());
+        ApiGateway::new(0, "/api").state(keycloak)
+    }
+
+    async fn status(app: Router, uri: &str, token: Option<String>) -> StatusCode {
+        let mut req = Request::get(uri);
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        send(app, req.body(Body::empty()).unwrap()).await.status()
+    }
+
+    #[tokio::test]
+    async fn test_modules() {
+        let minter = TokenMinter::new();
+        let keycloak: KeycloakClient = Arc::new(minter.keycloak());
+        let app = ApiGateway::new(0, "/api")
+            .module(ORDERS)
+            .module(CATALOG)
+            .state(keycloak)
+            .router(Router::new())
+            .unwrap();
+
+        assert_eq!(
+            status(app.clone(), "/api/catalog/1", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(app.clone(), "/api/orders/1", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let token = minter.token().roles(&["catalog"]).sign();
+        assert_eq!(
+            status(app.clone(), "/api/orders/1", Some(token)).await,
+            StatusCode::FORBIDDEN
+        );
+        let token = minter.token().roles(&["orders"]).sign();
+        assert_eq!(
+            status(app, "/api/orders/1", Some(token)).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_module_openapi() {
+        let app = gateway()
+            .openapi(ApiDoc::openapi())
+            .module(ORDERS)
+            .router(Router::new())
+            .unwrap();
+        let (_, spec) = send_json(app, test_util::get("/openapi.json")).await;
+        let operation = &spec["paths"]["/api/orders/{id}"]["get"];
+        assert_eq!(operation["tags"][1], "Orders");
+        assert_eq!(spec["tags"][0]["name"], "Orders");
+    }
+
+    #[test]
+    fn test_module_conflicts() {
+        let router = |gateway: ApiGateway, routes: Router| gateway.router(routes);
+        let nested = Module {
+            name: "order items",
+            path: "/orders/items",
+            roles: &[],
+        };
+        let res = router(gateway().module(ORDERS).module(nested), Router::new());
+        assert!(
+            matches!(res, Err(AxError::ApplicationStartup(message)) if message == "route module order items at /orders/items conflicts with route module orders at /orders")
+        );
+
+        let res = router(gateway().module(ORDERS).module(ORDERS), Router::new());
+        assert!(matches!(res, Err(AxError::ApplicationStartup(_))));
+
+        let ordering = Module {
+            name: "ordering",
+            path: "/ordering",
+            roles: &[],
+        };
+        let res = router(gateway().module(ORDERS).module(ordering), Router::new());
+        assert!(res.is_ok());
+
+        let versions = ApiVersions::new(VersionSelector::Path)
+            .version(ApiVersion::new("orders", Router::new()));
+        let res = router(gateway().module(ORDERS).versions(versions), Router::new());
+        assert!(
+            matches!(res, Err(AxError::ApplicationStartup(message)) if message == "API version orders at /orders conflicts with route module orders at /orders")
+        );
+
+        let res = router(ApiGateway::new(0, "/api").module(ORDERS), Router::new());
+        assert!(
+            matches!(res, Err(AxError::ApplicationStartup(message)) if message.starts_with("route module orders requires state"))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Overlapping method route")]
+    fn test_route_conflicts_with_module() {
+        let routes = Router::new().route("/orders/:id", get(|| async { "" }));
+        let _ = gateway().module(ORDERS).router(routes);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    fmt,
+    ops::Deref,
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+};
+
+use crate::errors::AxError;
+
+/// A type of state required by [`crate::ApiGateway::require_state`] or
+/// [`crate::routes::RouteModule::required_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateKey {
+    id: TypeId,
+    name: &'static str,
+}
+
+impl StateKey {
+    pub fn of<T: 'static>() -> Self {
+        Self {
+            id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Values shared by the handlers and middleware of the gateway, one per type, e.g. the
+/// `KeycloakClient`, a database pool or the service config. Set with
+/// [`crate::ApiGateway::state`], read with the [`State`] extractor or from the request
+/// extensions in middleware.
+#[derive(Clone, Default)]
+pub struct AppState {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `value`, replacing the value of the same type.
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    }
+
+    pub fn contains(&self, key: StateKey) -> bool {
+        self.values.contains_key(&key.id)
+    }
+
+    /// Fails when a value of `keys` is missing, naming its type.
+    pub(crate) fn check(&self, keys: &[StateKey], required_by: &str) -> Result<(), AxError> {
+        match keys.iter().find(|key| !self.contains(**key)) {
+            Some(key) => Err(AxError::ApplicationStartup(format!(
+                "{} requires state {} which is not set",
+                required_by,
+                key.name()
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppState")
+            .field("values", &self.values.len())
+            .finish()
+    }
+}
+
+/// A value of the [`AppState`], a 500 when it is not set.
+///
+/// ```ignore
+/// async fn list(State(pool): State<PgPool>) -> AxResult<Vec<Order>> { .. }
+///
+/// ApiGateway::new(8080, "/api").state(pool).require_state::<PgPool>()
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct State<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for State<T>
+where
+    T: Clone + Send + Sync + 'static,
+    B: Send,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        req.extensions()
+            .get::<AppState>()
+            .and_then(AppState::get::<T>)
+            .map(State)
+            .ok_or_else(|| {
+                AxError::InternalServerErrorWithContext(format!(
+                    "state {} is not configured",
+                    type_name::<T>()
+                ))
+            })
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get, Router};
+
+    use super::State;
+    use crate::{
+        config::GatewayConfig,
+        errors::AxError,
+        test_util::{self, send_text},
+        ApiGateway,
+    };
+
+    #[derive(Clone)]
+    struct Greeting(&'static str);
+
+    #[tokio::test]
+    async fn test_state() {
+        let routes = Router::new()
+            .route(
+                "/hello",
+                get(|State(Greeting(greeting)): State<Greeting>| async move { greeting }),
+            )
+            .route(
+                "/port",
+                get(|State(config): State<GatewayConfig>| async move { config.port.to_string() }),
+            )
+            .route(
+                "/count",
+                get(|State(count): State<u32>| async move { count.to_string() }),
+            );
+        let app = ApiGateway::new(8081, "/api")
+            .state(Greeting("hello"))
+            .require_state::<Greeting>()
+            .router(routes)
+            .unwrap();
+
+        assert_eq!(
+            send_text(app.clone(), test_util::get("/api/hello")).await,
+            (StatusCode::OK, "hello".to_owned())
+        );
+        assert_eq!(
+            send_text(app.clone(), test_util::get("/api/port")).await,
+            (StatusCode::OK, "8081".to_owned())
+        );
+        let (status, _) = send_text(app, test_util::get("/api/count")).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_required_state() {
+        let res = ApiGateway::new(0, "/api")
+            .require_state::<Greeting>()
+            .router(Router::new());
+        assert!(
+            matches!(res, Err(AxError::ApplicationStartup(message)) if message.starts_with("gateway requires state"))
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::env;
+
+use axum::{extract::MatchedPath, http::Request};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Sampler, Tracer},
+    Resource,
+};
+use serde::Deserialize;
+use tower_http::trace::MakeSpan;
+use tracing::{Level, Span};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+
+use crate::{context::RequestId, errors::AxError};
+
+/// OTLP/HTTP export of the request spans.
+///
+/// ```ignore
+/// let tracer = TelemetryConfig::from_env()?.install()?;
+/// tracing_subscriber::registry()
+///     .with(tracing_subscriber::fmt::layer())
+///     .with(telemetry::layer(tracer))
+///     .init();
+/// gateway.serve(routes).await?;
+/// telemetry::shutdown();
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub service_name: String,
+    /// Traces endpoint of the collector, including the `/v1/traces` path.
+    pub otlp_endpoint: String,
+    /// Fraction of new traces that are sampled, incoming sampling decisions are kept.
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            service_name: "axum-rest-api".to_owned(),
+            otlp_endpoint: "http://localhost:4318/v1/traces".to_owned(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn new(service_name: &str) -> Self {
+        Self {
+            service_name: service_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Reads the standard `OTEL_SERVICE_NAME`, `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`
+    /// and `OTEL_TRACES_SAMPLER_ARG` variables on top of the defaults.
+    pub fn from_env() -> Result<Self, AxError> {
+        let mut config = Self::default();
+        if let Ok(service_name) = env::var("OTEL_SERVICE_NAME") {
+            config.service_name = service_name;
+        }
+        if let Ok(endpoint) = env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") {
+            config.otlp_endpoint = endpoint;
+        }
+        if let Ok(ratio) = env::var("OTEL_TRACES_SAMPLER_ARG") {
+            config.sample_ratio = ratio.trim().parse().map_err(|_| {
+                AxError::ApplicationStartup(format!("invalid sample ratio {}", ratio))
+            })?;
+        }
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), AxError> {
+        if !(0.0..=1.0).contains(&self.sample_ratio) {
+            return Err(AxError::ApplicationStartup(
+                "sample_ratio must be between 0 and 1".to_owned(),
+            ));
+        }
+        if self.otlp_endpoint.parse::<axum::http::Uri>().is_err() {
+            return Err(AxError::ApplicationStartup(format!(
+                "invalid otlp endpoint {}",
+                self.otlp_endpoint
+            )));
+        }
+        Ok(())
+    }
+
+    /// Installs the W3C trace-context propagator and a batching OTLP exporter as the
+    /// global tracer provider. Must be called inside the tokio runtime.
+    pub fn install(&self) -> Result<Tracer, AxError> {
+        self.validate()?;
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(&self.otlp_endpoint),
+            )
+            .with_trace_config(
+                trace::config()
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                        self.sample_ratio,
+                    ))))
+                    .with_resource(Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        self.service_name.clone(),
+                    )])),
+            )
+            .install_batch(runtime::Tokio)
+            .map_err(|err| AxError::ApplicationStartup(format!("failed to start tracer: {}", err)))
+    }
+}
+
+/// `tracing` layer exporting spans through `tracer`.
+pub fn layer<S>(tracer: Tracer) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+/// Flushes the spans not exported yet, call once the server has stopped.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Request span of the gateway [`tower_http::trace::TraceLayer`], continuing the trace
+/// of incoming `traceparent`/`tracestate` headers.
+#[derive(Debug, Clone)]
+pub(crate) struct MakeRequestSpan {
+    level: Level,
+    include_headers: bool,
+}
+
+impl MakeRequestSpan {
+    pub(crate) fn new(level: Level, include_headers: bool) -> Self {
+        Self {
+            level,
+            include_headers,
+        }
+    }
+}
+
+impl<B> MakeSpan<B> for MakeRequestSpan {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(RequestId::as_str)
+            .unwrap_or_default();
+        let name = match req.extensions().get::<MatchedPath>() {
+            Some(path) => format!("{} {}", req.method(), path.as_str()),
+            None => req.method().to_string(),
+        };
+        // fields of `DefaultMakeSpan` plus the request id, the level of a span has to be a constant
+        macro_rules! make_span {
+            ($level:expr) => {
+                if self.include_headers {
+                    tracing::span!(
+                        $level,
+                        "request",
+                        method = %req.method(),
+                        uri = %req.uri(),
+                        version = ?req.version(),
+                        request_id,
+                        headers = ?req.headers(),
+                        otel.name = %name,
+                        otel.kind = "server",
+                    )
+                } else {
+                    tracing::span!(
+                        $level,
+                        "request",
+                        method = %req.method(),
+                        uri = %req.uri(),
+                        version = ?req.version(),
+                        request_id,
+                        otel.name = %name,
+                        otel.kind = "server",
+                    )
+                }
+            };
+        }
+        let span = match self.level {
+            Level::ERROR => make_span!(Level::ERROR),
+            Level::WARN => make_span!(Level::WARN),
+            Level::INFO => make_span!(Level::INFO),
+            Level::DEBUG => make_span!(Level::DEBUG),
+            Level::TRACE => make_span!(Level::TRACE),
+        };
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        span.set_parent(parent);
+        span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::get,
+        Extension, Router,
+    };
+    use futures_util::future::BoxFuture;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Server,
+    };
+    use keycloak::{testing::MockKeycloak, KeycloakClient};
+    use opentelemetry::{
+        global,
+        trace::{TraceContextExt, Tracer as _, TracerProvider as _},
+    };
+    use opentelemetry_sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        propagation::TraceContextPropagator,
+        trace::TracerProvider,
+    };
+    use tracing_subscriber::prelude::*;
+
+    use super::{layer, TelemetryConfig};
+    use crate::{test_util::send, ApiGateway};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+    #[derive(Debug, Clone, Default)]
+    struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for InMemoryExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trace_context_propagation() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let exporter = InMemoryExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber =
+            tracing_subscriber::registry().with(layer(provider.tracer("axum-rest-api")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let server = MockKeycloak::start().await;
+        let keycloak: KeycloakClient = Arc::new(server.client());
+        let routes = Router::new()
+            .route(
+                "/keys",
+                get(
+                    |Extension(keycloak): Extension<KeycloakClient>| async move {
+                        keycloak.load_keys().await.map_err(|err| err.to_string())
+                    },
+                ),
+            )
+            .layer(Extension(keycloak));
+        let app = ApiGateway::new(0, "/api").router(routes).unwrap();
+        let req = Request::get("/api/keys")
+            .header(
+                "traceparent",
+                format!("00-{}-{}-01", TRACE_ID, PARENT_SPAN_ID),
+            )
+            .body(Body::empty())
+            .unwrap();
+        // the request span closes with the response body, which `send` reads
+        let res = send(app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // the keycloak call continues the incoming trace
+        let headers = server.last_request_headers("/certs").unwrap();
+        let traceparent = headers["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+        assert!(!traceparent.contains(PARENT_SPAN_ID));
+
+        provider.force_flush();
+        let spans = exporter.0.lock().unwrap();
+        let span = spans
+            .iter()
+            .find(|span| span.name == "GET /api/keys")
+            .unwrap();
+        assert_eq!(span.span_context.trace_id().to_string(), TRACE_ID);
+        assert_eq!(span.parent_span_id.to_string(), PARENT_SPAN_ID);
+        assert!(traceparent.contains(&span.span_context.span_id().to_string()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_otlp_export() {
+        // collector stub counting the OTLP/HTTP exports it receives
+        let exports = Arc::new(Mutex::new(vec![]));
+        let received = exports.clone();
+        let make_service = make_service_fn(move |_| {
+            let received = received.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<hyper::Body>| {
+                    received.lock().unwrap().push((
+                        req.uri().path().to_owned(),
+                        req.headers()["content-type"].to_str().unwrap().to_owned(),
+                    ));
+                    async { Ok::<_, Infallible>(hyper::Response::new(hyper::Body::empty())) }
+                }))
+            }
+        });
+        let collector = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = collector.local_addr();
+        tokio::spawn(collector);
+
+        let config = TelemetryConfig {
+            otlp_endpoint: format!("http://{}/v1/traces", addr),
+            ..TelemetryConfig::new("test-service")
+        };
+        let tracer = config.install().unwrap();
+        tracer.in_span("exported", |cx| {
+            assert!(cx.span().span_context().is_sampled());
+        });
+        tokio::task::spawn_blocking(super::shutdown).await.unwrap();
+
+        let exports = exports.lock().unwrap();
+        assert_eq!(
+            exports.as_slice(),
+            [("/v1/traces".to_owned(), "application/x-protobuf".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_invalid_telemetry_config() {
+        let config = TelemetryConfig {
+            sample_ratio: 2.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(TelemetryConfig::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,39 @@
+//! Requests of the unit tests, sent to a router without binding a port.
+
+use axum::{
+    body::{Body, Bytes},
+    http::{Request, Response, StatusCode},
+    response, Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+pub(crate) fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+/// The response of `app` to `req`, with its body read.
+pub(crate) async fn send(app: Router, req: Request<Body>) -> Response<Bytes> {
+    read(app.oneshot(req).await.unwrap()).await
+}
+
+/// `res` with its body read.
+pub(crate) async fn read(res: response::Response) -> Response<Bytes> {
+    let (parts, body) = res.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap();
+    Response::from_parts(parts, body)
+}
+
+/// Status and JSON body of the response, `Value::Null` when the body is not JSON.
+pub(crate) async fn send_json(app: Router, req: Request<Body>) -> (StatusCode, Value) {
+    let res = send(app, req).await;
+    let body = serde_json::from_slice(res.body()).unwrap_or(Value::Null);
+    (res.status(), body)
+}
+
+/// Status and text body of the response.
+pub(crate) async fn send_text(app: Router, req: Request<Body>) -> (StatusCode, String) {
+    let res = send(app, req).await;
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    (res.status(), body)
+}
@@ -0,0 +1,544 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    Extension,
+};
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use futures_util::future::BoxFuture;
+use rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinHandle,
+};
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+use tracing::{error, info};
+use x509_parser::{
+    extensions::GeneralName,
+    prelude::{FromDer, X509Certificate},
+};
+
+use crate::errors::AxError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    /// Client certificates are not requested.
+    #[default]
+    Off,
+    /// Client certificates are verified when sent, anonymous clients are accepted.
+    Optional,
+    /// Connections without a valid client certificate are refused.
+    Required,
+}
+
+/// HTTPS settings of the gateway, PEM files on disk.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// CA bundle client certificates are verified against, required unless
+    /// `client_auth` is off.
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+    #[serde(default)]
+    pub client_auth: ClientAuth,
+    /// How often the PEM files are checked for changes, `0` disables hot reload.
+    #[serde(default = "default_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+}
+
+fn default_reload_interval_secs() -> u64 {
+    30
+}
+
+impl FromStr for ClientAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(ClientAuth::Off),
+            "optional" => Ok(ClientAuth::Optional),
+            "required" => Ok(ClientAuth::Required),
+            _ => Err(format!("unknown client auth {}", s)),
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn new<P: Into<PathBuf>>(cert_path: P, key_path: P) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+            client_auth: ClientAuth::Off,
+            reload_interval_secs: default_reload_interval_secs(),
+        }
+    }
+
+    pub fn client_auth<P: Into<PathBuf>>(mut self, client_auth: ClientAuth, ca_path: P) -> Self {
+        self.client_auth = client_auth;
+        self.client_ca_path = Some(ca_path.into());
+        self
+    }
+
+    /// Checks that the PEM files can be loaded.
+    pub fn validate(&self) -> Result<(), AxError> {
+        self.server_config().map(|_| ())
+    }
+
+    /// Loads the PEM files into a rustls config offering HTTP/2 and HTTP/1.1 over ALPN.
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>, AxError> {
+        self.build(vec![b"h2".to_vec(), b"http/1.1".to_vec()])
+    }
+
+    pub(crate) fn build(&self, alpn_protocols: Vec<Vec<u8>>) -> Result<Arc<ServerConfig>, AxError> {
+        let certs = read_certs(&self.cert_path)?;
+        let key = read_key(&self.key_path)?;
+        let verifier = match (self.client_auth, &self.client_ca_path) {
+            (ClientAuth::Off, _) => NoClientAuth::new(),
+            (client_auth, Some(ca_path)) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(ca_path)? {
+                    roots.add(&cert).map_err(|err| {
+                        AxError::ApplicationStartup(format!(
+                            "invalid ca certificate in {}: {}",
+                            ca_path.display(),
+                            err
+                        ))
+                    })?;
+                }
+                if client_auth == ClientAuth::Required {
+                    AllowAnyAuthenticatedClient::new(roots)
+                } else {
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+                }
+            }
+            (_, None) => {
+                return Err(AxError::ApplicationStartup(
+                    "client_ca_path is required to verify client certificates".to_owned(),
+                ))
+            }
+        };
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)
+            .map_err(|err| AxError::ApplicationStartup(format!("invalid tls config: {}", err)))?;
+        config.alpn_protocols = alpn_protocols;
+        Ok(Arc::new(config))
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        [
+            Some(&self.cert_path),
+            Some(&self.key_path),
+            self.client_ca_path.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| path.metadata().and_then(|meta| meta.modified()).ok())
+        .max()
+    }
+
+    /// Polls the PEM files and swaps the certificates in `rustls` when they change, until
+    /// the returned watch is dropped. A broken update is logged and the previous
+    /// certificates stay in use.
+    pub(crate) fn watch(
+        self,
+        rustls: RustlsConfig,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> Option<CertificateWatch> {
+        if self.reload_interval_secs == 0 {
+            return None;
+        }
+        let task = tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(self.reload_interval_secs));
+            let mut last_modified = self.modified();
+            loop {
+                interval.tick().await;
+                let modified = self.modified();
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                match self.build(alpn_protocols.clone()) {
+                    Ok(config) => {
+                        rustls.reload_from_config(config);
+                        info!("reloaded tls certificate {}", self.cert_path.display());
+                    }
+                    Err(err) => error!("failed to reload tls certificate: {}", err),
+                }
+            }
+        });
+        Some(CertificateWatch(task))
+    }
+}
+
+/// Task of [`TlsConfig::watch`], stopped when dropped.
+#[derive(Debug)]
+pub(crate) struct CertificateWatch(JoinHandle<()>);
+
+impl Drop for CertificateWatch {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, AxError> {
+    File::open(path).map(BufReader::new).map_err(|err| {
+        AxError::ApplicationStartup(format!("failed to read {}: {}", path.display(), err))
+    })
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, AxError> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).map_err(|err| {
+        AxError::ApplicationStartup(format!("invalid pem {}: {}", path.display(), err))
+    })?;
+    if certs.is_empty() {
+        return Err(AxError::ApplicationStartup(format!(
+            "no certificate found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey, AxError> {
+    let items = rustls_pemfile::read_all(&mut open(path)?).map_err(|err| {
+        AxError::ApplicationStartup(format!("invalid pem {}: {}", path.display(), err))
+    })?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            AxError::ApplicationStartup(format!("no private key found in {}", path.display()))
+        })
+}
+
+/// Certificate the client authenticated with, available to handlers on mTLS connections.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    der: Vec<u8>,
+    subject: String,
+    common_name: Option<String>,
+    dns_names: Vec<String>,
+    email_addresses: Vec<String>,
+    serial: String,
+}
+
+impl ClientCertificate {
+    pub(crate) fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_owned);
+        let alternative_names = match cert.subject_alternative_name() {
+            Ok(Some(san)) => san.value.general_names.clone(),
+            _ => vec![],
+        };
+        let dns_names = alternative_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect();
+        let email_addresses = alternative_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::RFC822Name(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect();
+        Some(Self {
+            der: der.to_vec(),
+            subject: cert.subject().to_string(),
+            common_name,
+            dns_names,
+            email_addresses,
+            serial: cert.raw_serial_as_string(),
+        })
+    }
+
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// Distinguished name, e.g. `CN=client.test`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn common_name(&self) -> Option<&str> {
+        self.common_name.as_deref()
+    }
+
+    /// DNS subject alternative names.
+    pub fn dns_names(&self) -> &[String] {
+        &self.dns_names
+    }
+
+    /// E-mail (RFC 822) subject alternative names.
+    pub fn email_addresses(&self) -> &[String] {
+        &self.email_addresses
+    }
+
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for ClientCertificate {
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        req.extensions()
+            .get::<Option<ClientCertificate>>()
+            .cloned()
+            .flatten()
+            .ok_or(AxError::Unauthorized)
+    }
+}
+
+/// Rustls acceptor that hands the peer certificate of each connection to its requests.
+#[derive(Clone)]
+pub(crate) struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub(crate) fn new(config: RustlsConfig) -> Self {
+        Self {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = <Extension<Option<ClientCertificate>> as Layer<S>>::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| ClientCertificate::from_der(&cert.0));
+            Ok((stream, Extension(certificate).layer(service)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, net::TcpListener, path::Path, sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{header::HOST, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use axum_server::Handle;
+    use rustls::{ClientConfig, RootCertStore, ServerName};
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    use super::{read_certs, read_key, ClientAuth, ClientCertificate, TlsConfig};
+    use crate::{config::GatewayConfig, errors::AxError, ApiGateway};
+
+    fn testdata(file: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(file)
+    }
+
+    fn tls_config(client_auth: ClientAuth) -> TlsConfig {
+        TlsConfig::new(testdata("server.pem"), testdata("server.key"))
+            .client_auth(client_auth, testdata("ca.pem"))
+    }
+
+    async fn whoami(
+        addr: std::net::SocketAddr,
+        client_cert: bool,
+    ) -> Result<(StatusCode, String), ()> {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(&testdata("ca.pem")).unwrap() {
+            roots.add(&cert).unwrap();
+        }
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config = if client_cert {
+            builder
+                .with_single_cert(
+                    read_certs(&testdata("client.pem")).unwrap(),
+                    read_key(&testdata("client.key")).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .map_err(|_| ())?;
+        let (mut sender, conn) = hyper::client::conn::handshake(stream)
+            .await
+            .map_err(|_| ())?;
+        tokio::spawn(conn);
+        let res = sender
+            .send_request(
+                Request::get("/api/whoami")
+                    .header(HOST, "localhost")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .map_err(|_| ())?;
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        Ok((status, String::from_utf8(body.to_vec()).unwrap()))
+    }
+
+    #[test]
+    fn test_client_certificate() {
+        let der = read_certs(&testdata("client.pem")).unwrap().remove(0);
+        let cert = ClientCertificate::from_der(&der.0).unwrap();
+        assert_eq!(cert.common_name(), Some("client.test"));
+        assert_eq!(cert.subject(), "CN=client.test");
+        assert_eq!(cert.dns_names(), ["client.test"]);
+        assert!(cert.email_addresses().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_tls_config() {
+        let invalid = [
+            TlsConfig::new(testdata("server.pem"), testdata("missing.key")),
+            TlsConfig::new(testdata("server.key"), testdata("server.key")),
+            TlsConfig {
+                client_auth: ClientAuth::Required,
+                ..TlsConfig::new(testdata("server.pem"), testdata("server.key"))
+            },
+        ];
+        for config in invalid {
+            assert!(matches!(
+                config.validate(),
+                Err(AxError::ApplicationStartup(_))
+            ));
+        }
+        assert!(tls_config(ClientAuth::Optional).validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_mtls() {
+        let config = GatewayConfig {
+            tls: Some(tls_config(ClientAuth::Required)),
+            ..Default::default()
+        };
+        let routes = Router::new().route(
+            "/whoami",
+            get(|cert: ClientCertificate| async move {
+                cert.common_name().unwrap_or_default().to_owned()
+            }),
+        );
+        let gateway = ApiGateway::with_config(config, "/api");
+        let app = gateway.router(routes).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = Handle::new();
+        let server = handle.clone();
+        tokio::spawn(async move {
+            gateway.serve_listener(listener, app, server).await.unwrap();
+        });
+
+        let (status, body) = whoami(addr, true).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "client.test");
+
+        // the handshake is refused without a client certificate
+        assert!(whoami(addr, false).await.is_err());
+        handle.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_reload_certificate() {
+        let dir = env::temp_dir().join(format!("axum-rest-api-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        // a certificate the client does not accept for localhost
+        fs::copy(testdata("client.pem"), &cert_path).unwrap();
+        fs::copy(testdata("client.key"), &key_path).unwrap();
+
+        let config = GatewayConfig {
+            tls: Some(TlsConfig {
+                reload_interval_secs: 1,
+                ..TlsConfig::new(cert_path.clone(), key_path.clone())
+            }),
+            ..Default::default()
+        };
+        let routes = Router::new().route("/whoami", get(|| async { "server.test" }));
+        let gateway = ApiGateway::with_config(config, "/api");
+        let app = gateway.router(routes).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = Handle::new();
+        let server = handle.clone();
+        tokio::spawn(async move {
+            gateway.serve_listener(listener, app, server).await.unwrap();
+        });
+        assert!(whoami(addr, false).await.is_err());
+
+        fs::copy(testdata("server.pem"), &cert_path).unwrap();
+        fs::copy(testdata("server.key"), &key_path).unwrap();
+        let mut res = Err(());
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            res = whoami(addr, false).await;
+            if res.is_ok() {
+                break;
+            }
+        }
+        assert_eq!(res, Ok((StatusCode::OK, "server.test".to_owned())));
+        handle.shutdown();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use std::{borrow::Cow, fmt, ops::Deref};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    BoxError,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use validator::ValidationErrorsKind;
+pub use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::{
+    errors::AxError,
+    extract::{Json, Query},
+};
+
+/// One failed rule, `field` is the path of the field, e.g. `address.zip` or `items[2].sku`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// Field errors of [`AxError::Validation`], rendered as an array in the error response.
+/// Handlers return them for checks of their own, e.g.
+/// `AxError::Validation(FieldErrors::new().add("email", "taken", "email is taken"))`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(mut self, field: &str, code: &str, message: &str) -> Self {
+        self.0.push(FieldError {
+            field: field.to_owned(),
+            code: code.to_owned(),
+            message: message.to_owned(),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn errors(&self) -> &[FieldError] {
+        &self.0
+    }
+
+    fn collect(&mut self, path: &str, errors: &ValidationErrors) {
+        let mut fields: Vec<_> = errors.errors().iter().collect();
+        fields.sort_by_key(|(field, _)| *field);
+        for (field, kind) in fields {
+            let field = if path.is_empty() {
+                field.to_string()
+            } else {
+                format!("{}.{}", path, field)
+            };
+            match kind {
+                ValidationErrorsKind::Field(errors) => {
+                    for error in errors {
+                        self.0.push(FieldError {
+                            message: error
+                                .message
+                                .as_ref()
+                                .map(Cow::to_string)
+                                .unwrap_or_else(|| format!("{} is invalid: {}", field, error.code)),
+                            code: error.code.to_string(),
+                            field: field.clone(),
+                        });
+                    }
+                }
+                ValidationErrorsKind::Struct(errors) => self.collect(&field, errors),
+                ValidationErrorsKind::List(items) => {
+                    for (index, errors) in items {
+                        self.collect(&format!("{}[{}]", field, index), errors);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<_> = self.0.iter().map(|error| error.field.as_str()).collect();
+        write!(f, "invalid fields: {}", fields.join(", "))
+    }
+}
+
+impl From<ValidationErrors> for FieldErrors {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut field_errors = Self::new();
+        field_errors.collect("", &errors);
+        field_errors
+    }
+}
+
+impl From<ValidationErrors> for AxError {
+    fn from(errors: ValidationErrors) -> Self {
+        AxError::Validation(errors.into())
+    }
+}
+
+/// JSON body checked with its [`Validate`] rules.
+///
+/// ```ignore
+/// #[derive(Deserialize, Validate)]
+/// struct Signup {
+///     #[validate(email)]
+///     email: String,
+///     #[validate(length(min = 8, message = "password is too short"))]
+///     password: String,
+/// }
+///
+/// async fn signup(ValidatedJson(signup): ValidatedJson<Signup>) -> AxResult<()> { .. }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    B: axum::body::HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req).await?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Query string checked with its [`Validate`] rules.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedQuery<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    B: Send,
+{
+    type Rejection = AxError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request(req).await?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+impl<T> Deref for ValidatedQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    use super::{Validate, ValidatedJson, ValidatedQuery};
+    use crate::test_util::{self, send_json};
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Address {
+        #[validate(length(equal = 5))]
+        zip: String,
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Signup {
+        #[validate(email)]
+        email: String,
+        #[validate(length(min = 8, message = "password is too short"))]
+        password: String,
+        #[validate]
+        address: Address,
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Search {
+        #[validate(range(min = 1, max = 100))]
+        limit: u32,
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/signup",
+                post(|ValidatedJson(signup): ValidatedJson<Signup>| async move { signup.email }),
+            )
+            .route(
+                "/search",
+                get(|search: ValidatedQuery<Search>| async move { search.limit.to_string() }),
+            )
+    }
+
+    #[tokio::test]
+    async fn test_validated_json() {
+        let signup = |body: Value| {
+            Request::post("/signup")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let (status, body) = send_json(
+            app(),
+            signup(json!({
+                "email": "not-an-email",
+                "password": "short",
+                "address": { "zip": "123" }
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error_code"], "REQUEST_VALIDATION_FAILED");
+        assert_eq!(
+            body["errors"],
+            json!([
+                {
+                    "field": "address.zip",
+                    "code": "length",
+                    "message": "address.zip is invalid: length"
+                },
+                {
+                    "field": "email",
+                    "code": "email",
+                    "message": "email is invalid: email"
+                },
+                {
+                    "field": "password",
+                    "code": "length",
+                    "message": "password is too short"
+                }
+            ])
+        );
+
+        let (status, _) = send_json(
+            app(),
+            signup(json!({
+                "email": "jane@example.com",
+                "password": "long enough",
+                "address": { "zip": "12345" }
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_validated_query() {
+        let (status, body) = send_json(app(), test_util::get("/search?limit=500")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"][0]["field"], "limit");
+        assert_eq!(body["errors"][0]["code"], "range");
+
+        let (status, _) = send_json(app(), test_util::get("/search?limit=20")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
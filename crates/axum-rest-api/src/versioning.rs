@@ -0,0 +1,506 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    http::{
+        header::{ACCEPT, LINK, VARY},
+        uri::PathAndQuery,
+        HeaderMap, HeaderName, HeaderValue, Request, Uri,
+    },
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use tower::Layer;
+
+use crate::errors::{AxError, ErrorHook};
+
+pub static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+pub static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// How a request picks the [`ApiVersion`] it is served by. Versions are always
+/// reachable by path, e.g. `/api/v1/items`; with `Header` and `Accept` a request
+/// without the version in its path, e.g. `/api/items`, gets the requested version or
+/// the default one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSelector {
+    /// `/api/v2/items`
+    Path,
+    /// `X-Api-Version: v2` or `X-Api-Version: 2` for `X-Api-Version`.
+    Header(HeaderName),
+    /// `Accept: application/json; version=2`
+    Accept,
+}
+
+/// Marks an [`ApiVersion`] as deprecated, its responses get a `Deprecation` header
+/// (RFC 9745) and optionally `Sunset` (RFC 8594) and a `Link` to the migration guide.
+#[derive(Debug, Clone, Default)]
+pub struct Deprecation {
+    since: Option<SystemTime>,
+    sunset: Option<SystemTime>,
+    link: Option<String>,
+}
+
+impl Deprecation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// When the version was deprecated, `Deprecation: true` when unknown.
+    pub fn since(mut self, since: SystemTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// When the version stops being served.
+    pub fn sunset(mut self, sunset: SystemTime) -> Self {
+        self.sunset = Some(sunset);
+        self
+    }
+
+    pub fn link(mut self, link: &str) -> Self {
+        self.link = Some(link.to_owned());
+        self
+    }
+
+    fn headers(&self) -> Result<Vec<(HeaderName, HeaderValue)>, AxError> {
+        let deprecation = match self.since {
+            Some(since) => {
+                let secs = since
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_secs())
+                    .unwrap_or_default();
+                format!("@{}", secs)
+            }
+            None => "true".to_owned(),
+        };
+        let mut headers = vec![(DEPRECATION.clone(), header_value(&deprecation)?)];
+        if let Some(sunset) = self.sunset {
+            let sunset = httpdate::fmt_http_date(sunset);
+            headers.push((SUNSET.clone(), header_value(&sunset)?));
+        }
+        if let Some(link) = &self.link {
+            let link = format!(r#"<{}>; rel="deprecation"; type="text/html""#, link);
+            headers.push((LINK, header_value(&link)?));
+        }
+        Ok(headers)
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, AxError> {
+    HeaderValue::from_str(value).map_err(|_| {
+        AxError::ApplicationStartup(format!("invalid deprecation header value {}", value))
+    })
+}
+
+/// Routes of one version of the API, nested under `/<name>` below the root path.
+#[derive(Debug, Clone)]
+pub struct ApiVersion {
+    name: String,
+    routes: Router,
+    deprecation: Option<Deprecation>,
+}
+
+impl ApiVersion {
+    pub fn new(name: &str, routes: Router) -> Self {
+        Self {
+            name: name.to_owned(),
+            routes,
+            deprecation: None,
+        }
+    }
+
+    pub fn deprecated(mut self, deprecation: Deprecation) -> Self {
+        self.deprecation = Some(deprecation);
+        self
+    }
+}
+
+/// The versions of the API served side by side, see [`crate::ApiGateway::versions`].
+///
+/// ```ignore
+/// let selector = VersionSelector::Header(HeaderName::from_static("x-api-version"));
+/// let versions = ApiVersions::new(selector)
+///     .version(ApiVersion::new("v1", v1_routes).deprecated(Deprecation::new().sunset(sunset)))
+///     .version(ApiVersion::new("v2", v2_routes));
+/// ApiGateway::new(8080, "/api").versions(versions).serve(Router::new()).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ApiVersions {
+    selector: VersionSelector,
+    versions: Vec<ApiVersion>,
+    default: Option<String>,
+}
+
+impl ApiVersions {
+    pub fn new(selector: VersionSelector) -> Self {
+        Self {
+            selector,
+            versions: vec![],
+            default: None,
+        }
+    }
+
+    pub fn version(mut self, version: ApiVersion) -> Self {
+        self.versions.push(version);
+        self
+    }
+
+    /// Version of the requests that do not ask for one, the last added version
+    /// unless set.
+    pub fn default_version(mut self, name: &str) -> Self {
+        self.default = Some(name.to_owned());
+        self
+    }
+
+    pub fn selector(&self) -> &VersionSelector {
+        &self.selector
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.versions.iter().map(|version| version.name.as_str())
+    }
+
+    /// Every version nested under its name, with the deprecation headers.
+    pub(crate) fn routes(&self) -> Result<Router, AxError> {
+        if self.versions.is_empty() {
+            return Err(AxError::ApplicationStartup(
+                "at least one API version is required".to_owned(),
+            ));
+        }
+        let mut router = Router::new();
+        for (index, version) in self.versions.iter().enumerate() {
+            if version.name.is_empty() || version.name.contains('/') {
+                return Err(AxError::ApplicationStartup(format!(
+                    "invalid API version name {}",
+                    version.name
+                )));
+            }
+            if self.versions[..index]
+                .iter()
+                .any(|other| other.name == version.name)
+            {
+                return Err(AxError::ApplicationStartup(format!(
+                    "API version {} is added twice",
+                    version.name
+                )));
+            }
+            let mut routes = version.routes.clone();
+            if let Some(deprecation) = &version.deprecation {
+                let headers = Arc::new(deprecation.headers()?);
+                routes = routes.layer(middleware::from_fn(move |req, next| {
+                    deprecation_headers(req, next, headers.clone())
+                }));
+            }
+            router = router.nest(&format!("/{}", version.name), routes);
+        }
+        if let Some(default) = &self.default {
+            if !self.versions.iter().any(|version| &version.name == default) {
+                return Err(AxError::ApplicationStartup(format!(
+                    "default API version {} is not added",
+                    default
+                )));
+            }
+        }
+        Ok(router)
+    }
+
+    /// Wraps `app` to move requests below `root_path` that do not name a version in
+    /// their path to the version selected by their headers. The gateway middleware
+    /// sees the moved request.
+    pub(crate) fn select(self, app: Router, root_path: &str) -> Router {
+        if self.selector == VersionSelector::Path {
+            return app;
+        }
+        let names: Vec<_> = self
+            .versions
+            .into_iter()
+            .map(|version| version.name)
+            .collect();
+        let selection = Arc::new(Selection {
+            selector: self.selector,
+            default: self
+                .default
+                .unwrap_or_else(|| names[names.len() - 1].clone()),
+            names,
+        });
+        let root_path = root_path.trim_end_matches('/').to_owned();
+        Router::new().fallback(
+            middleware::from_fn(move |req, next| {
+                select_version(req, next, selection.clone(), root_path.clone())
+            })
+            .layer(app),
+        )
+    }
+}
+
+/// What [`select_version`] needs of [`ApiVersions`], without the routes.
+struct Selection {
+    selector: VersionSelector,
+    names: Vec<String>,
+    default: String,
+}
+
+impl Selection {
+    /// Name of the version asked for by the headers, the default one when none is.
+    /// Fails with the requested version when it is not served.
+    fn requested(&self, headers: &HeaderMap) -> Result<&str, String> {
+        let requested = match &self.selector {
+            VersionSelector::Path => None,
+            VersionSelector::Header(name) => headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim),
+            VersionSelector::Accept => headers
+                .get_all(ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .flat_map(|media_range| media_range.split(';').skip(1))
+                .find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    (name.trim() == "version").then(|| value.trim().trim_matches('"'))
+                }),
+        };
+        let Some(requested) = requested else {
+            return Ok(&self.default);
+        };
+        // `2` asks for `v2`
+        self.names
+            .iter()
+            .find(|name| *name == requested || name.strip_prefix('v') == Some(requested))
+            .map(String::as_str)
+            .ok_or_else(|| requested.to_owned())
+    }
+
+    fn vary(&self) -> Option<HeaderValue> {
+        match &self.selector {
+            VersionSelector::Header(name) => HeaderValue::from_str(name.as_str()).ok(),
+            _ => Some(HeaderValue::from_static("accept")),
+        }
+    }
+}
+
+/// Request extension left by [`select_version`] when the headers ask for a version
+/// which is not served.
+#[derive(Debug, Clone)]
+struct UnsupportedVersion(String);
+
+/// Moves the request to the selected version before it is routed. Requests for a
+/// version which is not served are rejected by [`selected_version`].
+async fn select_version<B>(
+    mut req: Request<B>,
+    next: Next<B>,
+    selection: Arc<Selection>,
+    root_path: String,
+) -> Response {
+    let rest = match req.uri().path().strip_prefix(&root_path) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.to_owned(),
+        _ => return next.run(req).await,
+    };
+    let segment = rest.trim_start_matches('/').split('/').next();
+    if selection
+        .names
+        .iter()
+        .any(|name| Some(name.as_str()) == segment)
+    {
+        return next.run(req).await;
+    }
+
+    let version = match selection.requested(req.headers()) {
+        Ok(version) => version,
+        Err(version) => {
+            req.extensions_mut().insert(UnsupportedVersion(version));
+            return next.run(req).await;
+        }
+    };
+    let mut path = format!("{}/{}{}", root_path, version, rest);
+    if let Some(query) = req.uri().query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = PathAndQuery::try_from(path).ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+
+    let mut res = next.run(req).await;
+    // after the CORS layer, which replaces `Vary`
+    if let Some(vary) = selection.vary() {
+        res.headers_mut().append(VARY, vary);
+    }
+    res
+}
+
+/// Innermost gateway middleware, rejects the requests [`select_version`] found no
+/// version for.
+pub(crate) async fn selected_version<B>(
+    req: Request<B>,
+    next: Next<B>,
+    hook: ErrorHook,
+) -> Response {
+    match req.extensions().get::<UnsupportedVersion>() {
+        Some(UnsupportedVersion(version)) => hook.render(AxError::BadRequest(format!(
+            "unsupported API version {}",
+            version
+        ))),
+        None => next.run(req).await,
+    }
+}
+
+async fn deprecation_headers<B>(
+    req: Request<B>,
+    next: Next<B>,
+    headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+) -> Response {
+    let mut res = next.run(req).await;
+    for (name, value) in headers.iter() {
+        res.headers_mut().append(name.clone(), value.clone());
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use axum::{
+        body::{Body, Bytes},
+        http::{
+            header::{LINK, VARY},
+            HeaderName, Request, Response, StatusCode,
+        },
+        routing::get,
+        Router,
+    };
+    use serde_json::Value;
+
+    use super::{ApiVersion, ApiVersions, Deprecation, VersionSelector, DEPRECATION, SUNSET};
+    use crate::{context::X_REQUEST_ID, errors::AxError, test_util, ApiGateway};
+
+    fn versions(selector: VersionSelector) -> ApiVersions {
+        let deprecation = Deprecation::new()
+            .since(UNIX_EPOCH + Duration::from_secs(1_688_169_599))
+            .sunset(UNIX_EPOCH + Duration::from_secs(1_735_689_600))
+            .link("https://example.com/migrate");
+        ApiVersions::new(selector)
+            .version(
+                ApiVersion::new("v1", Router::new().route("/items", get(|| async { "v1" })))
+                    .deprecated(deprecation),
+            )
+            .version(ApiVersion::new(
+                "v2",
+                Router::new().route("/items", get(|| async { "v2" })),
+            ))
+    }
+
+    async fn send(versions: ApiVersions, req: Request<Body>) -> (Response<Bytes>, String) {
+        let app = ApiGateway::new(0, "/api")
+            .versions(versions)
+            .router(Router::new())
+            .unwrap();
+        let res = test_util::send(app, req).await;
+        let body = String::from_utf8(res.body().to_vec()).unwrap();
+        (res, body)
+    }
+
+    #[tokio::test]
+    async fn test_path_versions() {
+        let get = test_util::get;
+        let (res, body) = send(versions(VersionSelector::Path), get("/api/v1/items")).await;
+        assert_eq!(body, "v1");
+        assert_eq!(res.headers()[&DEPRECATION], "@1688169599");
+        assert_eq!(res.headers()[&SUNSET], "Wed, 01 Jan 2025 00:00:00 GMT");
+        assert_eq!(
+            res.headers()[LINK],
+            r#"<https://example.com/migrate>; rel="deprecation"; type="text/html""#
+        );
+
+        let (res, body) = send(versions(VersionSelector::Path), get("/api/v2/items")).await;
+        assert_eq!(body, "v2");
+        assert!(!res.headers().contains_key(&DEPRECATION));
+
+        let (res, _) = send(versions(VersionSelector::Path), get("/api/items")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_header_versions() {
+        let selector = VersionSelector::Header(HeaderName::from_static("x-api-version"));
+        let get = |version: Option<&str>| {
+            let mut req = Request::get("/api/items");
+            if let Some(version) = version {
+                req = req.header("x-api-version", version);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        let (res, body) = send(versions(selector.clone()), get(Some("1"))).await;
+        assert_eq!(body, "v1");
+        assert!(res.headers().contains_key(&DEPRECATION));
+        assert!(res
+            .headers()
+            .get_all(VARY)
+            .iter()
+            .any(|vary| vary == "x-api-version"));
+
+        let (_, body) = send(versions(selector.clone()), get(None)).await;
+        assert_eq!(body, "v2");
+        let (_, body) = send(versions(selector.clone()).default_version("v1"), get(None)).await;
+        assert_eq!(body, "v1");
+
+        let (res, body) = send(versions(selector), get(Some("v9"))).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        // rendered inside the gateway middleware
+        assert!(res.headers().contains_key(&X_REQUEST_ID));
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error_message"], "unsupported API version v9");
+    }
+
+    #[tokio::test]
+    async fn test_accept_versions() {
+        let req = Request::get("/api/items")
+            .header("accept", "application/json; version=1")
+            .body(Body::empty())
+            .unwrap();
+        let (res, body) = send(versions(VersionSelector::Accept), req).await;
+        assert_eq!(body, "v1");
+        assert!(res
+            .headers()
+            .get_all(VARY)
+            .iter()
+            .any(|vary| vary == "accept"));
+
+        // the path wins over the header
+        let req = Request::get("/api/v2/items")
+            .header("accept", "application/json; version=1")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = send(versions(VersionSelector::Accept), req).await;
+        assert_eq!(body, "v2");
+    }
+
+    #[test]
+    fn test_invalid_versions() {
+        let router = |versions: ApiVersions| {
+            ApiGateway::new(0, "/api")
+                .versions(versions)
+                .router(Router::new())
+        };
+        assert!(matches!(
+            router(ApiVersions::new(VersionSelector::Path)),
+            Err(AxError::ApplicationStartup(_))
+        ));
+        assert!(matches!(
+            router(versions(VersionSelector::Path).version(ApiVersion::new("v2", Router::new()))),
+            Err(AxError::ApplicationStartup(_))
+        ));
+        assert!(matches!(
+            router(versions(VersionSelector::Path).default_version("v3")),
+            Err(AxError::ApplicationStartup(_))
+        ));
+    }
+}
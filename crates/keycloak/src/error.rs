@@ -1,29 +1,187 @@
-use thiserror::Error;
+use std::time::{Duration, SystemTime};
 
-// TODO: Using error code in reqwest::error and message
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Response, StatusCode,
+};
+use serde::Deserialize;
+use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum KeycloakError {
+    /// Any `401` of Keycloak, e.g. wrong client or user credentials on the token endpoint.
     #[error("unauthorized access")]
     UnAuthorized,
+    #[error("forbidden: {0}")]
+    Forbidden(String),
     #[error("failed to acquire write lock")]
     WriteLockFailed,
     #[error("failed to acquire read lock")]
     ReadLockFailed,
     #[error("{0} not found")]
     ConfigNotFound(String),
+    #[error("invalid grant: {0}")]
+    InvalidGrant(String),
+    #[error("invalid client: {0}")]
+    InvalidClient(String),
+    #[error("user already exists: {0}")]
+    UserExists(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("resource not found: {0}")]
+    NotFound(String),
+    #[error("rate limited by keycloak")]
+    RateLimited(Option<Duration>),
+    #[error("keycloak is unavailable")]
+    Unavailable(Option<Duration>),
+    #[error("circuit breaker is open, keycloak calls are suspended for {0:?}")]
+    CircuitOpen(Duration),
+    #[error("oauth error {error} ({status}): {description}")]
+    OAuth {
+        status: StatusCode,
+        error: String,
+        description: String,
+    },
     #[error("response error with status code: {0}")]
-    ResponseError(reqwest::StatusCode, String),
+    ResponseError(StatusCode, String),
     #[error("request error: {0}")]
     RequestError(reqwest::Error),
     #[error("jwt error: {0}")]
     JWTError(jsonwebtoken::errors::Error),
     #[error("serde error: {0}")]
     SerdeError(serde_urlencoded::ser::Error),
+    #[error("invalid jwks: {0}")]
+    InvalidJwks(serde_json::Error),
+    #[error("io error: {0}")]
+    IoError(std::io::Error),
     #[error("error: {0}")]
     Other(String),
 }
 
+/// Error body of Keycloak: `{error, error_description}` of the OpenID Connect endpoints
+/// (`/token`, `/token/introspect`, ...), `{errorMessage}` or `{error}` of the admin REST
+/// API (`/admin/realms/...`).
+#[derive(Debug, Default, Deserialize)]
+struct ErrorBody {
+    error: Option<String>,
+    error_description: Option<String>,
+    #[serde(rename = "errorMessage")]
+    error_message: Option<String>,
+}
+
+impl ErrorBody {
+    /// The OAuth error code, which unlike the messages of the admin API has no spaces.
+    fn oauth_error(&self) -> Option<&str> {
+        self.error
+            .as_deref()
+            .filter(|error| !error.is_empty() && !error.contains(char::is_whitespace))
+    }
+
+    /// Message of the body, `body` itself when it is not a known error body.
+    fn details(self, body: &str) -> String {
+        match (self.error_message, self.error, self.error_description) {
+            (Some(message), _, _) => message,
+            (None, Some(error), Some(description)) => format!("{}: {}", error, description),
+            (None, Some(error), None) => error,
+            (None, None, _) => body.to_owned(),
+        }
+    }
+}
+
+impl KeycloakError {
+    /// Builds an error from a non-success Keycloak response, consuming its body.
+    pub(crate) async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let retry_after = retry_after(response.headers());
+        match response.text().await {
+            Ok(body) => Self::from_body(status, retry_after, &body),
+            Err(err) => err.into(),
+        }
+    }
+
+    /// Maps a status code and raw body onto a typed variant. The status decides first,
+    /// the body refines the other statuses into OAuth errors, falling back to
+    /// [`KeycloakError::ResponseError`] when it is not a known Keycloak error.
+    pub(crate) fn from_body(status: StatusCode, retry_after: Option<Duration>, body: &str) -> Self {
+        let parsed = serde_json::from_str::<ErrorBody>(body).unwrap_or_default();
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => KeycloakError::RateLimited(retry_after),
+            StatusCode::SERVICE_UNAVAILABLE => KeycloakError::Unavailable(retry_after),
+            StatusCode::UNAUTHORIZED => KeycloakError::UnAuthorized,
+            StatusCode::FORBIDDEN => KeycloakError::Forbidden(parsed.details(body)),
+            StatusCode::NOT_FOUND => KeycloakError::NotFound(parsed.details(body)),
+            StatusCode::CONFLICT => {
+                let details = parsed.details(body);
+                if details.starts_with("User exists") {
+                    KeycloakError::UserExists(details)
+                } else {
+                    KeycloakError::Conflict(details)
+                }
+            }
+            _ => match parsed.oauth_error() {
+                Some("invalid_grant") => {
+                    KeycloakError::InvalidGrant(parsed.error_description.unwrap_or_default())
+                }
+                Some("invalid_client" | "unauthorized_client") => {
+                    KeycloakError::InvalidClient(parsed.error_description.unwrap_or_default())
+                }
+                Some(error) => KeycloakError::OAuth {
+                    status,
+                    error: error.to_owned(),
+                    description: parsed.error_description.unwrap_or_default(),
+                },
+                None => KeycloakError::ResponseError(status, parsed.details(body)),
+            },
+        }
+    }
+
+    /// Whether the failed call may succeed if it is sent again unchanged.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            KeycloakError::RateLimited(_) => true,
+            _ => self.is_outage(),
+        }
+    }
+
+    /// Delay Keycloak asked for through a `Retry-After` header, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            KeycloakError::RateLimited(retry_after) | KeycloakError::Unavailable(retry_after) => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the error means Keycloak itself is unreachable or failing; these are the
+    /// errors counted by the circuit breaker.
+    pub(crate) fn is_outage(&self) -> bool {
+        match self {
+            KeycloakError::Unavailable(_) => true,
+            KeycloakError::RequestError(err) => err.is_timeout() || err.is_connect(),
+            KeycloakError::ResponseError(status, _) | KeycloakError::OAuth { status, .. } => {
+                status.is_server_error()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `Retry-After` as delay-seconds or as an HTTP-date, which is no delay once past.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            Some(
+                date.duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO),
+            )
+        }
+    }
+}
+
 impl From<reqwest::Error> for KeycloakError {
     fn from(value: reqwest::Error) -> Self {
         KeycloakError::RequestError(value)
@@ -41,3 +199,148 @@ impl From<jsonwebtoken::errors::Error> for KeycloakError {
         KeycloakError::JWTError(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, RETRY_AFTER},
+        StatusCode,
+    };
+
+    use super::{retry_after, KeycloakError};
+
+    #[test]
+    fn test_oauth_errors() {
+        let err = KeycloakError::from_body(
+            StatusCode::BAD_REQUEST,
+            None,
+            r#"{"error":"invalid_grant","error_description":"Invalid refresh token"}"#,
+        );
+        assert!(
+            matches!(err, KeycloakError::InvalidGrant(ref msg) if msg == "Invalid refresh token")
+        );
+        assert!(!err.is_retryable());
+
+        let err = KeycloakError::from_body(
+            StatusCode::BAD_REQUEST,
+            None,
+            r#"{"error":"unauthorized_client","error_description":"Client not allowed for direct access grants"}"#,
+        );
+        assert!(matches!(err, KeycloakError::InvalidClient(_)));
+
+        let err = KeycloakError::from_body(
+            StatusCode::BAD_REQUEST,
+            None,
+            r#"{"error":"unsupported_grant_type"}"#,
+        );
+        assert!(
+            matches!(err, KeycloakError::OAuth { ref error, .. } if error == "unsupported_grant_type")
+        );
+
+        // 401s of the token endpoint stay `UnAuthorized`, whatever their OAuth error
+        for body in [
+            r#"{"error":"invalid_client","error_description":"Invalid client or Invalid client credentials"}"#,
+            r#"{"error":"invalid_grant","error_description":"Invalid user credentials"}"#,
+            r#"{"error":"unauthorized_client","error_description":"Invalid client secret"}"#,
+        ] {
+            let err = KeycloakError::from_body(StatusCode::UNAUTHORIZED, None, body);
+            assert!(matches!(err, KeycloakError::UnAuthorized));
+        }
+    }
+
+    #[test]
+    fn test_admin_errors() {
+        let err = KeycloakError::from_body(
+            StatusCode::CONFLICT,
+            None,
+            r#"{"errorMessage":"User exists with same username"}"#,
+        );
+        assert!(matches!(err, KeycloakError::UserExists(_)));
+
+        let err = KeycloakError::from_body(
+            StatusCode::CONFLICT,
+            None,
+            r#"{"errorMessage":"Group already exists"}"#,
+        );
+        assert!(matches!(err, KeycloakError::Conflict(_)));
+
+        let err =
+            KeycloakError::from_body(StatusCode::NOT_FOUND, None, r#"{"error":"User not found"}"#);
+        assert!(matches!(err, KeycloakError::NotFound(ref msg) if msg == "User not found"));
+
+        let err = KeycloakError::from_body(
+            StatusCode::NOT_FOUND,
+            None,
+            r#"{"error":"HTTP 404 Not Found"}"#,
+        );
+        assert!(matches!(err, KeycloakError::NotFound(ref msg) if msg == "HTTP 404 Not Found"));
+
+        let err = KeycloakError::from_body(
+            StatusCode::FORBIDDEN,
+            None,
+            r#"{"error":"HTTP 403 Forbidden"}"#,
+        );
+        assert!(matches!(err, KeycloakError::Forbidden(ref msg) if msg == "HTTP 403 Forbidden"));
+
+        let err = KeycloakError::from_body(
+            StatusCode::BAD_REQUEST,
+            None,
+            r#"{"error":"HTTP 400 Bad Request"}"#,
+        );
+        assert!(matches!(
+            err,
+            KeycloakError::ResponseError(StatusCode::BAD_REQUEST, ref msg) if msg == "HTTP 400 Bad Request"
+        ));
+    }
+
+    #[test]
+    fn test_fallback_and_retryable() {
+        let err = KeycloakError::from_body(StatusCode::UNAUTHORIZED, None, "");
+        assert!(matches!(err, KeycloakError::UnAuthorized));
+
+        let err =
+            KeycloakError::from_body(StatusCode::BAD_GATEWAY, None, "<html>bad gateway</html>");
+        assert!(matches!(
+            err,
+            KeycloakError::ResponseError(StatusCode::BAD_GATEWAY, _)
+        ));
+        assert!(err.is_retryable());
+
+        let err = KeycloakError::from_body(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(3)),
+            "",
+        );
+        assert!(matches!(err, KeycloakError::RateLimited(Some(d)) if d == Duration::from_secs(3)));
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(3)));
+
+        let err = KeycloakError::from_body(StatusCode::SERVICE_UNAVAILABLE, None, "");
+        assert!(matches!(err, KeycloakError::Unavailable(None)));
+        assert!(err.is_outage());
+
+        let err = KeycloakError::CircuitOpen(Duration::from_secs(1));
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(118) && delay <= Duration::from_secs(120));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+}
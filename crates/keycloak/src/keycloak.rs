@@ -1,15 +1,18 @@
 use super::error::KeycloakError;
+use super::retry::{CircuitBreaker, RetryPolicy};
 use super::types::*;
 use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
-use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use reqwest::{header::CONTENT_TYPE, Client, RequestBuilder, Response};
 use serde::{de::DeserializeOwned, Deserialize};
-use std::{collections::HashMap, env, sync::RwLock};
+use std::{collections::HashMap, env, fs, path::Path, sync::RwLock};
 
 #[derive(Debug, Deserialize)]
 struct CertKey {
     kid: String,
-    n: String,
-    e: String,
+    #[serde(rename = "use")]
+    key_use: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -17,14 +20,48 @@ struct Keys {
     keys: Vec<CertKey>,
 }
 
+impl Keys {
+    /// RSA signing keys of the set; encryption and non RSA keys are skipped.
+    fn into_verifying_keys(self) -> HashMap<String, VerifyingKey> {
+        self.keys
+            .into_iter()
+            .filter(|key| key.key_use.as_deref() != Some("enc"))
+            .filter_map(|key| match (key.n, key.e) {
+                (Some(n), Some(e)) => Some((key.kid, VerifyingKey::Rsa { n, e })),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Public key used to verify token signatures, registered under its `kid`.
+#[derive(Debug, Clone)]
+pub enum VerifyingKey {
+    /// RSA modulus and exponent, base64url encoded as in a JWKS document.
+    Rsa { n: String, e: String },
+    /// RSA public key in PEM format.
+    RsaPem(Vec<u8>),
+}
+
+impl VerifyingKey {
+    pub(crate) fn decoding_key(&self) -> Result<DecodingKey, KeycloakError> {
+        match self {
+            VerifyingKey::Rsa { n, e } => Ok(DecodingKey::from_rsa_components(n, e)?),
+            VerifyingKey::RsaPem(pem) => Ok(DecodingKey::from_rsa_pem(pem)?),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Keycloak {
     client_id: String,
     client_secret: String,
     endpoint: String,
     admin_url: String,
-    cert_keys: RwLock<Option<HashMap<String, CertKey>>>,
+    cert_keys: RwLock<Option<HashMap<String, VerifyingKey>>>,
     token: RwLock<Option<TokenResponse>>,
+    retry_policy: RetryPolicy,
+    circuit_breaker: CircuitBreaker,
 }
 
 impl Keycloak {
@@ -36,6 +73,8 @@ impl Keycloak {
             admin_url: format!("{}/admin/realms/{}", url, realm),
             cert_keys: RwLock::new(None),
             token: RwLock::new(None),
+            retry_policy: RetryPolicy::default(),
+            circuit_breaker: CircuitBreaker::default(),
         }
     }
 
@@ -65,14 +104,68 @@ impl Keycloak {
             Err(_) => return Err(KeycloakError::ConfigNotFound("KEYCLOAK_URL".to_owned())),
         };
 
-        Ok(Self {
-            client_id,
-            client_secret,
-            endpoint: format!("{}/realms/{}/protocol/openid-connect", url, realm),
-            admin_url: format!("{}/admin/realms/{}", url, realm),
-            cert_keys: RwLock::new(None),
-            token: RwLock::new(None),
-        })
+        Ok(Self::new(client_id, client_secret, realm, url))
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.circuit_breaker
+    }
+
+    /// Sends the request built by `request`, mapping non-success responses to a
+    /// [`KeycloakError`]. Idempotent calls are retried according to the retry policy;
+    /// every call goes through the circuit breaker.
+    async fn send<F>(&self, idempotent: bool, request: F) -> Result<Response, KeycloakError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let max_retries = if idempotent {
+            self.retry_policy.max_retries()
+        } else {
+            0
+        };
+        let mut attempt = 0;
+        loop {
+            self.circuit_breaker.check()?;
+            let request = request();
+            #[cfg(feature = "opentelemetry")]
+            let request = request.headers(crate::telemetry::trace_headers());
+            let result = match request.send().await {
+                Ok(response) if response.status().is_success() => Ok(response),
+                Ok(response) => Err(KeycloakError::from_response(response).await),
+                Err(err) => Err(KeycloakError::from(err)),
+            };
+            let err = match result {
+                Ok(response) => {
+                    self.circuit_breaker.record_success();
+                    return Ok(response);
+                }
+                Err(err) => err,
+            };
+            if err.is_outage() {
+                self.circuit_breaker.record_failure();
+            } else {
+                // Keycloak answered, e.g. the trial call of a half-open circuit got a 404
+                self.circuit_breaker.record_success();
+            }
+            if attempt >= max_retries || !err.is_retryable() {
+                return Err(err);
+            }
+            match self.retry_policy.backoff(attempt, &err) {
+                Some(backoff) => tokio::time::sleep(backoff).await,
+                None => return Err(err),
+            }
+            attempt += 1;
+        }
     }
 
     // TODO: Using this to optimize performance of client SC token
@@ -96,29 +189,62 @@ impl Keycloak {
 
     pub async fn load_keys(&self) -> Result<(), KeycloakError> {
         let url = format!("{}/certs", self.endpoint);
-        println!("loading keys: {}", url);
-        let response = Client::new().get(url).send().await?;
-        match response.status() {
-            StatusCode::OK => {
-                let cert_keys = response.json::<Keys>().await?;
-                match self.cert_keys.write() {
-                    Ok(mut lock) => {
-                        *lock = Some(
-                            cert_keys
-                                .keys
-                                .into_iter()
-                                .map(|key| (key.kid.clone(), key))
-                                .collect(),
-                        );
-                        Ok(())
-                    }
-                    Err(_) => Err(KeycloakError::WriteLockFailed),
-                }
+        let client = Client::new();
+        // on failure the previously loaded keys are kept, so `decode` keeps working
+        // while keycloak is down
+        let response = self.send(true, || client.get(&url)).await?;
+        let cert_keys = response.json::<Keys>().await?;
+        self.set_keys(cert_keys.into_verifying_keys())
+    }
+
+    /// Checks that keycloak answers, with a single request for its JWKS which leaves
+    /// the loaded keys alone.
+    pub async fn ping(&self) -> Result<(), KeycloakError> {
+        let url = format!("{}/certs", self.endpoint);
+        let client = Client::new();
+        self.send(false, || client.get(&url)).await?;
+        Ok(())
+    }
+
+    /// Replaces the signing keys with the ones of a JWKS document, without contacting
+    /// keycloak.
+    pub fn load_jwks(&self, jwks: &str) -> Result<(), KeycloakError> {
+        let cert_keys = serde_json::from_str::<Keys>(jwks).map_err(KeycloakError::InvalidJwks)?;
+        self.set_keys(cert_keys.into_verifying_keys())
+    }
+
+    pub fn load_jwks_file<P: AsRef<Path>>(&self, path: P) -> Result<(), KeycloakError> {
+        let jwks = fs::read_to_string(path).map_err(KeycloakError::IoError)?;
+        self.load_jwks(&jwks)
+    }
+
+    /// Registers an RSA public key in PEM format under `kid`, next to the keys
+    /// already loaded.
+    pub fn add_pem_key(&self, kid: &str, pem: &[u8]) -> Result<(), KeycloakError> {
+        let key = VerifyingKey::RsaPem(pem.to_vec());
+        key.decoding_key()?;
+        self.add_key(kid, key)
+    }
+
+    pub fn add_key(&self, kid: &str, key: VerifyingKey) -> Result<(), KeycloakError> {
+        match self.cert_keys.write() {
+            Ok(mut lock) => {
+                lock.get_or_insert_with(HashMap::new)
+                    .insert(kid.to_owned(), key);
+                Ok(())
             }
-            _ => Err(KeycloakError::ResponseError(
-                response.status(),
-                response.text().await?,
-            )),
+            Err(_) => Err(KeycloakError::WriteLockFailed),
+        }
+    }
+
+    /// Replaces all signing keys with an in-memory set.
+    pub fn set_keys(&self, keys: HashMap<String, VerifyingKey>) -> Result<(), KeycloakError> {
+        match self.cert_keys.write() {
+            Ok(mut lock) => {
+                *lock = Some(keys);
+                Ok(())
+            }
+            Err(_) => Err(KeycloakError::WriteLockFailed),
         }
     }
 
@@ -139,46 +265,39 @@ impl Keycloak {
             .client_secret(client_secret);
         let data = serde_urlencoded::to_string(request)?;
         let url = format!("{}/token", self.endpoint);
-        let response = Client::new()
-            .post(url)
-            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-            .body(data)
-            .send()
+        let client = Client::new();
+        let response = self
+            .send(true, || {
+                client
+                    .post(&url)
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(data.clone())
+            })
             .await?;
-        match response.status() {
-            StatusCode::OK => Ok(response.json::<TokenResponse>().await?),
-            StatusCode::UNAUTHORIZED => Err(KeycloakError::UnAuthorized),
-            _ => Err(KeycloakError::ResponseError(
-                response.status(),
-                response.text().await?,
-            )),
-        }
+        Ok(response.json::<TokenResponse>().await?)
     }
 
     pub async fn get_oauth2_token(
         &self,
         request: TokenRequest,
     ) -> Result<TokenResponse, KeycloakError> {
+        let idempotent = matches!(request.grant_type, GrantType::ClientCredentials);
         let data = serde_urlencoded::to_string(
             request
                 .client_id(&self.client_id)
                 .client_secret(&self.client_secret),
         )?;
         let url = format!("{}/token", self.endpoint);
-        let response = Client::new()
-            .post(url)
-            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-            .body(data)
-            .send()
+        let client = Client::new();
+        let response = self
+            .send(idempotent, || {
+                client
+                    .post(&url)
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(data.clone())
+            })
             .await?;
-        match response.status() {
-            StatusCode::OK => Ok(response.json::<TokenResponse>().await?),
-            StatusCode::UNAUTHORIZED => Err(KeycloakError::UnAuthorized),
-            _ => Err(KeycloakError::ResponseError(
-                response.status(),
-                response.text().await?,
-            )),
-        }
+        Ok(response.json::<TokenResponse>().await?)
     }
 
     pub async fn verify_token(&self, token: String) -> Result<TokenVerifyResponse, KeycloakError> {
@@ -188,37 +307,30 @@ impl Keycloak {
             client_secret: self.client_secret.clone(),
         })?;
         let url = format!("{}/token/introspect", self.endpoint);
-        let response = Client::new()
-            .post(url)
-            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-            .body(data)
-            .send()
+        let client = Client::new();
+        let response = self
+            .send(true, || {
+                client
+                    .post(&url)
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(data.clone())
+            })
             .await?;
-        match response.status() {
-            StatusCode::OK => Ok(response.json::<TokenVerifyResponse>().await?),
-            _ => Err(KeycloakError::ResponseError(
-                response.status(),
-                response.text().await?,
-            )),
-        }
+        Ok(response.json::<TokenVerifyResponse>().await?)
     }
 
     pub async fn register_user(&self, user: &CreateUserRequest) -> Result<(), KeycloakError> {
         let token = self.get_oauth2_token(TokenRequest::client()).await?;
         let url = format!("{}/users", self.admin_url);
-        let response = Client::new()
-            .post(url)
-            .bearer_auth(token.access_token)
-            .json(&user)
-            .send()
-            .await?;
-        match response.status() {
-            StatusCode::CREATED => Ok(()),
-            _ => Err(KeycloakError::ResponseError(
-                response.status(),
-                response.text().await?,
-            )),
-        }
+        let client = Client::new();
+        self.send(false, || {
+            client
+                .post(&url)
+                .bearer_auth(&token.access_token)
+                .json(&user)
+        })
+        .await?;
+        Ok(())
     }
 
     pub fn decode<T: DeserializeOwned>(&self, token: String) -> Result<T, KeycloakError> {
@@ -230,21 +342,17 @@ impl Keycloak {
                     None => return Err(KeycloakError::Other("KID not specified".to_owned())),
                 };
                 let read = &self.cert_keys.read();
-                let (n, e) = match read {
+                let key = match read {
                     Ok(guard) => match &(**guard) {
                         Some(cert_keys) => match cert_keys.get(&kid) {
-                            Some(key) => Ok((&key.n, &key.e)),
+                            Some(key) => key.decoding_key(),
                             None => Err(KeycloakError::Other("Key id not found".to_owned())),
                         },
                         None => Err(KeycloakError::Other("Cert Key Empty".to_owned())),
                     },
                     Err(_) => Err(KeycloakError::ReadLockFailed),
                 }?;
-                let token = decode::<T>(
-                    &token,
-                    &DecodingKey::from_rsa_components(n, e)?,
-                    &Validation::new(Algorithm::RS256),
-                )?;
+                let token = decode::<T>(&token, &key, &Validation::new(Algorithm::RS256))?;
                 Ok(token.claims)
             }
             _ => Err(KeycloakError::Other("Algorithm Not Supported".to_owned())),
@@ -256,57 +364,71 @@ impl Keycloak {
 
 #[cfg(test)]
 mod tests {
-    use crate::keycloak::types::CreateUserRequest;
-    use crate::keycloak::types::TokenRequest;
+    use crate::types::CreateUserRequest;
+    use crate::types::TokenRequest;
     use crate::TokenClaim;
 
     use super::KeycloakError;
 
     use super::Keycloak;
-    use anyhow::Result;
+    use crate::testing::{Failure, MockKeycloak, MockUser};
+    use crate::{CircuitBreaker, RetryPolicy};
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use reqwest::StatusCode;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const PRIVATE_KEY: &str = include_str!("../testdata/private.pem");
+    const PUBLIC_KEY: &str = include_str!("../testdata/public.pem");
+    const JWKS: &str = include_str!("../testdata/jwks.json");
 
     const EXPIRED_TOKEN: &str = "eyJhbGciOiJSUzI1NiIsInR5cCIgOiAiSldUIiwia2lkIiA6ICJHc205MTFLTTR3YmV4Y2VjTGhrYnp5MnlvdkVIblIyV0pmMTNLekNKbkdVIn0.eyJleHAiOjE2NTU3NDY2NjEsImlhdCI6MTY1NTc0NjM2MSwianRpIjoiODgwZDJkMzUtYjQ0My00YjkyLWEwZjctYmQ2MzhjYWQ4N2Y5IiwiaXNzIjoiaHR0cHM6Ly9pZGVudGl0eS5iaXpvZnQuaWQvYXV0aC9yZWFsbXMvY3J5cHRvLXRpcHBpbmciLCJhdWQiOiJhY2NvdW50Iiwic3ViIjoiYTk5YTIzZDgtYzc1ZS00YTE5LWFkZWMtMmMyNmMyNmE3NGNkIiwidHlwIjoiQmVhcmVyIiwiYXpwIjoiYmFja2VuZC1zZXJ2aWNlIiwic2Vzc2lvbl9zdGF0ZSI6IjE2YTc3NzZmLTk5MmQtNGIzOC1iZmJlLTVlYmNlYmZkNWRjMyIsImFjciI6IjEiLCJyZWFsbV9hY2Nlc3MiOnsicm9sZXMiOlsib2ZmbGluZV9hY2Nlc3MiLCJ1bWFfYXV0aG9yaXphdGlvbiJdfSwicmVzb3VyY2VfYWNjZXNzIjp7ImFjY291bnQiOnsicm9sZXMiOlsibWFuYWdlLWFjY291bnQiLCJtYW5hZ2UtYWNjb3VudC1saW5rcyIsInZpZXctcHJvZmlsZSJdfX0sInNjb3BlIjoicHJvZmlsZSBlbWFpbCIsImVtYWlsX3ZlcmlmaWVkIjp0cnVlLCJuYW1lIjoiQW5kcmlhbnRvIEt1cm5pYXdhbiIsInByZWZlcnJlZF91c2VybmFtZSI6ImFuZHJpeDIxQGdtYWlsLmNvbSIsImdpdmVuX25hbWUiOiJBbmRyaWFudG8iLCJmYW1pbHlfbmFtZSI6Ikt1cm5pYXdhbiIsImVtYWlsIjoiYW5kcml4MjFAZ21haWwuY29tIn0.VzHzC9h377p4EZALKzw6Evi0iv2IEagBj2hFdjvO8I5GvcibNe1YGvE8EZVhC-U9MU2XGUFYCI4Bm5MKS-xarHdpHKb52xWYUqle9L1JLhr6tJ2tZoCoyQRNQrdBKA9zkQ5_ajiH-HOdxGzms-79-z-NotvkNk0BClYsAhQ90q-8C-r9uBZQgwO_rpER9CvsATvWi897lObH0ZNAT-NkfxcITa3ZCzjkzjqiZazl5VO64Y8-_0Eo1s3ys3oiCGrnQJu4QtsXx8u5NHIEZF_U17X7GUmkvDr8yW1s7nTkiv1Iamt3cU9GBULppZWNltO1XePuB5ggHTmg_PyJy-6_Ow";
+    async fn mock_server() -> MockKeycloak {
+        let server = MockKeycloak::start().await;
+        server.add_user(MockUser::new("andrix21@gmail.com", "saya").email("andrix21@gmail.com"));
+        server
+    }
+
     #[tokio::test]
     async fn test_login() -> Result<(), KeycloakError> {
-        dotenv().ok();
-        let client = Keycloak::new_from_env()?;
+        let server = mock_server().await;
+        let client = server.client();
         let request = TokenRequest {
             username: Some("andrix21@gmail.com".to_owned()),
             password: Some("saya".to_owned()),
             ..Default::default()
         };
         let res = client.get_oauth2_token(request).await?;
-        println!("{:?}", res);
         assert_ne!(res.access_token, "");
+
+        let wrong_password =
+            TokenRequest::username_password("andrix21@gmail.com".to_owned(), "salah".to_owned());
+        let res = client.get_oauth2_token(wrong_password).await;
+        assert!(matches!(res, Err(KeycloakError::UnAuthorized)));
         Ok(())
     }
 
     #[tokio::test]
     async fn test_load_certs() -> Result<(), KeycloakError> {
-        dotenv().ok();
-        let client = Keycloak::new_from_env()?;
+        let server = mock_server().await;
+        let client = server.client();
         client.load_keys().await?;
-        println!("{:?}", client);
-        let x = match client.cert_keys.read() {
-            Ok(guard) => {
-                assert_eq!((*guard).is_none(), false);
-                if let Some(cert_keys) = &(*guard) {
-                    assert!(cert_keys.len() > 0);
-                }
-                Ok(())
-            }
-            Err(_) => Err(KeycloakError::ReadLockFailed),
-        };
-        x
+        let guard = client
+            .cert_keys
+            .read()
+            .map_err(|_| KeycloakError::ReadLockFailed)?;
+        assert!((*guard).is_some());
+        if let Some(cert_keys) = &(*guard) {
+            assert!(!cert_keys.is_empty());
+        }
+        Ok(())
     }
 
     #[tokio::test]
     async fn test_verify() -> Result<(), KeycloakError> {
-        dotenv().ok();
-        let client = Keycloak::new_from_env()?;
+        let server = mock_server().await;
+        let client = server.client();
         let res = client.verify_token(EXPIRED_TOKEN.to_owned()).await?;
-        println!("{:?}", res);
-        assert_eq!(res.active, false);
+        assert!(!res.active);
 
         let request = TokenRequest {
             username: Some("andrix21@gmail.com".to_owned()),
@@ -316,15 +438,14 @@ mod tests {
         let res2 = client.get_oauth2_token(request).await?;
 
         let res = client.verify_token(res2.access_token.to_owned()).await?;
-        println!("{:?}", res);
-        assert_eq!(res.active, true);
+        assert!(res.active);
         Ok(())
     }
 
     #[tokio::test]
     async fn test_register_user() -> Result<(), KeycloakError> {
-        dotenv().ok();
-        let client = Keycloak::new_from_env()?;
+        let server = mock_server().await;
+        let client = server.client();
 
         let request = CreateUserRequest::new(
             "First".to_owned(),
@@ -334,27 +455,191 @@ mod tests {
             "anjinggalak".to_owned(),
         );
         client.register_user(&request).await?;
+        assert_eq!(server.user("username").unwrap().password, "anjinggalak");
 
+        let res = client.register_user(&request).await;
+        assert!(matches!(res, Err(KeycloakError::UserExists(_))));
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_decode() -> Result<(), KeycloakError> {
-        dotenv().ok();
-        let client = Keycloak::new_from_env()?;
+    async fn test_decode_issued_token() -> Result<(), KeycloakError> {
+        let server = mock_server().await;
+        let client = server.client();
         client.load_keys().await?;
 
-        let request = TokenRequest {
-            username: Some("andrix21@gmail.com".to_owned()),
-            password: Some("saya".to_owned()),
+        let res = client
+            .get_oauth2_token(TokenRequest::username_password(
+                "andrix21@gmail.com".to_owned(),
+                "saya".to_owned(),
+            ))
+            .await?;
+        let claims = client.decode::<TokenClaim>(res.access_token)?;
+        assert_eq!(claims.email, "andrix21@gmail.com");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_idempotent_calls() -> Result<(), KeycloakError> {
+        let server = mock_server().await;
+        let client = server
+            .client()
+            .with_retry_policy(RetryPolicy::new(2).initial_backoff(Duration::from_millis(1)));
+
+        server.fail_next(2, Failure::Unavailable(Some(0)));
+        client.load_keys().await?;
+        assert_eq!(server.request_count("/certs"), 3);
+
+        server.fail_next(3, Failure::InternalError);
+        let res = client.load_keys().await;
+        assert!(matches!(
+            res,
+            Err(KeycloakError::ResponseError(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                _
+            ))
+        ));
+        assert_eq!(server.request_count("/certs"), 6);
+
+        // client credential tokens are retried, password grants are sent once
+        server.fail_next(1, Failure::RateLimited(Some(0)));
+        client.get_oauth2_token(TokenRequest::client()).await?;
+        assert_eq!(server.request_count("/token"), 2);
+
+        // a Retry-After beyond the max backoff fails the call
+        server.fail_next(1, Failure::RateLimited(Some(60)));
+        let res = client.get_oauth2_token(TokenRequest::client()).await;
+        assert!(matches!(res, Err(KeycloakError::RateLimited(Some(_)))));
+        assert_eq!(server.request_count("/token"), 3);
+
+        server.fail_next(1, Failure::Unavailable(None));
+        let res = client
+            .get_oauth2_token(TokenRequest::username_password(
+                "andrix21@gmail.com".to_owned(),
+                "saya".to_owned(),
+            ))
+            .await;
+        assert!(matches!(res, Err(KeycloakError::Unavailable(None))));
+        assert_eq!(server.request_count("/token"), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_keeps_cached_keys() -> Result<(), KeycloakError> {
+        let server = mock_server().await;
+        let client = server
+            .client()
+            .with_retry_policy(RetryPolicy::none())
+            .with_circuit_breaker(CircuitBreaker::new(2, Duration::from_secs(60)));
+        client.load_keys().await?;
+        let token = client
+            .get_oauth2_token(TokenRequest::username_password(
+                "andrix21@gmail.com".to_owned(),
+                "saya".to_owned(),
+            ))
+            .await?;
+
+        server.fail_always(Failure::Unavailable(None));
+        assert!(client.load_keys().await.is_err());
+        assert!(client.load_keys().await.is_err());
+        assert!(client.circuit_breaker().is_open());
+
+        let res = client.load_keys().await;
+        assert!(matches!(res, Err(KeycloakError::CircuitOpen(_))));
+        assert_eq!(server.request_count("/certs"), 3);
+
+        let claims = client.decode::<TokenClaim>(token.access_token)?;
+        assert_eq!(claims.email, "andrix21@gmail.com");
+        Ok(())
+    }
+
+    fn offline_client() -> Keycloak {
+        Keycloak::new(
+            "client".to_owned(),
+            "secret".to_owned(),
+            "realm".to_owned(),
+            "http://localhost:0".to_owned(),
+        )
+    }
+
+    fn sign_token(kid: &str) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let claims = TokenClaim {
+            iat: now,
+            exp: now + 300,
+            azp: "client".to_owned(),
+            given_name: "First".to_owned(),
+            family_name: "Second".to_owned(),
+            email: "ini@email.com".to_owned(),
             ..Default::default()
         };
-        let res = client.get_oauth2_token(request).await?;
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_owned());
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_decode() -> Result<(), KeycloakError> {
+        let client = offline_client();
+        client.load_jwks(JWKS)?;
+
+        let claims = client.decode::<TokenClaim>(sign_token("test-key"))?;
+        assert_eq!(claims.email, "ini@email.com");
+
+        let unknown = client.decode::<TokenClaim>(sign_token("other-key"));
+        assert!(matches!(unknown, Err(KeycloakError::Other(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_with_pem_key() -> Result<(), KeycloakError> {
+        let client = offline_client();
+        assert!(client.decode::<TokenClaim>(sign_token("pem-key")).is_err());
+
+        client.add_pem_key("pem-key", PUBLIC_KEY.as_bytes())?;
+        let claims = client.decode::<TokenClaim>(sign_token("pem-key"))?;
+        assert_eq!(claims.azp, "client");
+
+        assert!(client.add_pem_key("bad-key", b"not a pem").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_jwks_skips_unusable_keys() -> Result<(), KeycloakError> {
+        let client = offline_client();
+        client.load_jwks(
+            r#"{"keys":[
+                {"kid":"ec","kty":"EC","crv":"P-256","x":"x","y":"y"},
+                {"kid":"enc","kty":"RSA","use":"enc","n":"n","e":"AQAB"},
+                {"kid":"sig","kty":"RSA","use":"sig","n":"n","e":"AQAB"}
+            ]}"#,
+        )?;
+        let guard = client
+            .cert_keys
+            .read()
+            .map_err(|_| KeycloakError::ReadLockFailed)?;
+        let keys = guard.as_ref().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys.contains_key("sig"));
+        Ok(())
+    }
 
-        let claims = client.decode::<TokenClaim>(res.access_token.to_owned())?;
+    #[test]
+    fn test_load_jwks_file() -> Result<(), KeycloakError> {
+        let client = offline_client();
+        client.load_jwks_file(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/jwks.json"))?;
+        client.decode::<TokenClaim>(sign_token("test-key"))?;
 
-        println!("{:?}", claims);
-        assert_ne!(claims.email, "");
+        let missing = client.load_jwks_file("testdata/missing.json");
+        assert!(matches!(missing, Err(KeycloakError::IoError(_))));
         Ok(())
     }
 }
@@ -1,9 +1,16 @@
 pub mod error;
 mod keycloak;
+mod retry;
+#[cfg(feature = "opentelemetry")]
+mod telemetry;
 use std::sync::Arc;
 mod token_claim;
-pub use keycloak::Keycloak;
-pub use token_claim::TokenClaim;
+pub use keycloak::{Keycloak, VerifyingKey};
+pub use retry::{CircuitBreaker, RetryPolicy};
+pub use token_claim::{Access, TokenClaim};
 pub mod types;
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 pub type KeycloakClient = Arc<Keycloak>;
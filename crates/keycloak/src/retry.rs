@@ -0,0 +1,225 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use super::error::KeycloakError;
+
+/// Exponential backoff applied to idempotent Keycloak calls (certs, introspection,
+/// admin GETs and client-credential tokens).
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Default::default()
+        }
+    }
+
+    /// Disables retries, every call is sent exactly once.
+    pub fn none() -> Self {
+        Self::new(0)
+    }
+
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// Delay before retry number `attempt` (starting at 0). A `Retry-After` sent by
+    /// Keycloak takes precedence over the computed backoff, `None` when it is longer
+    /// than the max backoff and the call should fail instead.
+    pub(crate) fn backoff(&self, attempt: u32, error: &KeycloakError) -> Option<Duration> {
+        if let Some(retry_after) = error.retry_after() {
+            return (retry_after <= self.max_backoff).then_some(retry_after);
+        }
+        let exp = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        if self.jitter {
+            // full jitter: a random delay in [0, exp]
+            Some(rand::thread_rng().gen_range(Duration::ZERO..=exp))
+        } else {
+            Some(exp)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Fails calls fast with [`KeycloakError::CircuitOpen`] once Keycloak looks down.
+///
+/// After `failure_threshold` consecutive outage errors the circuit opens for
+/// `open_duration`. After that a single trial call is let through while the others
+/// keep failing for another `open_duration`; a success closes the circuit again and a
+/// failure re-opens it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30))
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        match self.state.lock() {
+            Ok(state) => matches!(state.open_until, Some(until) if until > Instant::now()),
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<(), KeycloakError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| KeycloakError::WriteLockFailed)?;
+        let now = Instant::now();
+        match state.open_until {
+            Some(until) if until > now => Err(KeycloakError::CircuitOpen(until - now)),
+            Some(_) => {
+                // half-open: this call is the trial, re-armed in case it never finishes
+                state.open_until = Some(now + self.open_duration);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = BreakerState::default();
+        }
+    }
+
+    pub(crate) fn record_failure(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+            if state.consecutive_failures >= self.failure_threshold {
+                state.open_until = Some(Instant::now() + self.open_duration);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+
+    use super::{CircuitBreaker, RetryPolicy};
+    use crate::error::KeycloakError;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(5)
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(350))
+            .jitter(false);
+        let err = KeycloakError::ResponseError(StatusCode::BAD_GATEWAY, String::new());
+        assert_eq!(policy.backoff(0, &err), Some(Duration::from_millis(100)));
+        assert_eq!(policy.backoff(1, &err), Some(Duration::from_millis(200)));
+        assert_eq!(policy.backoff(2, &err), Some(Duration::from_millis(350)));
+        assert_eq!(policy.backoff(40, &err), Some(Duration::from_millis(350)));
+
+        let jittered = policy.jitter(true);
+        for attempt in 0..5 {
+            assert!(jittered.backoff(attempt, &err).unwrap() <= Duration::from_millis(350));
+        }
+
+        let rate_limited = KeycloakError::RateLimited(Some(Duration::from_millis(300)));
+        assert_eq!(
+            jittered.backoff(0, &rate_limited),
+            Some(Duration::from_millis(300))
+        );
+        // a longer Retry-After is not waited for
+        let rate_limited = KeycloakError::RateLimited(Some(Duration::from_secs(2)));
+        assert_eq!(jittered.backoff(0, &rate_limited), None);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        assert!(breaker.check().is_ok());
+
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        assert!(matches!(
+            breaker.check(),
+            Err(KeycloakError::CircuitOpen(_))
+        ));
+
+        std::thread::sleep(Duration::from_millis(60));
+        // half-open: one trial call goes through, a failure re-opens immediately
+        assert!(breaker.check().is_ok());
+        assert!(matches!(
+            breaker.check(),
+            Err(KeycloakError::CircuitOpen(_))
+        ));
+        breaker.record_failure();
+        assert!(breaker.is_open());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+    }
+}
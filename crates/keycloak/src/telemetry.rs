@@ -0,0 +1,15 @@
+use opentelemetry::global;
+use opentelemetry_http::HeaderInjector;
+use reqwest::header::HeaderMap;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Context of the current `tracing` span, encoded by the global propagator, so the
+/// Keycloak request joins the caller's trace.
+pub(crate) fn trace_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
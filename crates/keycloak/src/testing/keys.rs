@@ -0,0 +1,97 @@
+use std::sync::{Arc, OnceLock};
+
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rsa::{
+    pkcs1::{EncodeRsaPrivateKey, LineEnding},
+    pkcs8::EncodePublicKey,
+    traits::PublicKeyParts,
+    RsaPrivateKey, RsaPublicKey,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::VerifyingKey;
+
+const KEY_BITS: usize = 2048;
+
+/// RSA key pair used to sign test tokens, published under `kid`.
+#[derive(Clone)]
+pub struct TestKeyPair {
+    kid: String,
+    encoding_key: EncodingKey,
+    public_pem: String,
+    n: String,
+    e: String,
+}
+
+impl TestKeyPair {
+    /// Generates a fresh 2048 bit key pair.
+    pub fn generate(kid: &str) -> Self {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+            .expect("failed to generate rsa key");
+        let public_key = RsaPublicKey::from(&private_key);
+        let private_pem = private_key
+            .to_pkcs1_pem(LineEnding::LF)
+            .expect("failed to encode rsa private key");
+        let public_pem = public_key
+            .to_public_key_pem(LineEnding::LF)
+            .expect("failed to encode rsa public key");
+
+        Self {
+            kid: kid.to_owned(),
+            encoding_key: EncodingKey::from_rsa_pem(private_pem.as_bytes())
+                .expect("failed to load generated rsa key"),
+            public_pem,
+            n: base64::encode_config(public_key.n().to_bytes_be(), base64::URL_SAFE_NO_PAD),
+            e: base64::encode_config(public_key.e().to_bytes_be(), base64::URL_SAFE_NO_PAD),
+        }
+    }
+
+    /// Key pair generated once per process; key generation is slow in debug builds.
+    pub fn shared() -> Arc<Self> {
+        static SHARED: OnceLock<Arc<TestKeyPair>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| Arc::new(Self::generate("test-key")))
+            .clone()
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn public_pem(&self) -> &str {
+        &self.public_pem
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey::Rsa {
+            n: self.n.clone(),
+            e: self.e.clone(),
+        }
+    }
+
+    /// JWKS document publishing the public key, as served by `/certs`.
+    pub fn jwks(&self) -> Value {
+        json!({
+            "keys": [{
+                "kid": self.kid,
+                "kty": "RSA",
+                "alg": "RS256",
+                "use": "sig",
+                "n": self.n,
+                "e": self.e,
+            }]
+        })
+    }
+
+    /// Signs `claims` with RS256, setting `kid` in the header.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> String {
+        self.sign_with_kid(&self.kid, claims)
+    }
+
+    pub fn sign_with_kid<T: Serialize>(&self, kid: &str, claims: &T) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_owned());
+        encode(&header, claims, &self.encoding_key).expect("failed to sign token")
+    }
+}
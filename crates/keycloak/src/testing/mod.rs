@@ -0,0 +1,12 @@
+//! Helpers for testing code that talks to keycloak without a live server.
+//!
+//! Enabled with the `testing` feature. [`MockKeycloak`] starts a local OIDC/admin
+//! server that signs tokens with a generated RSA key, [`TokenMinter`] signs tokens
+//! directly for code that only needs [`crate::Keycloak::decode`] to accept them.
+mod keys;
+mod server;
+mod token;
+
+pub use keys::TestKeyPair;
+pub use server::{Failure, MockKeycloak, MockUser};
+pub use token::{TestToken, TokenMinter};
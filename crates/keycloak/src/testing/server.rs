@@ -0,0 +1,546 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{
+    body,
+    header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE, LOCATION, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use jsonwebtoken::{decode, Algorithm, Validation};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use super::{keys::TestKeyPair, token::TestToken};
+use crate::Keycloak;
+
+const ACCESS_TOKEN_LIFESPAN: u64 = 300;
+const REFRESH_TOKEN_LIFESPAN: u64 = 1800;
+
+/// User known to the mock server.
+#[derive(Debug, Clone, Default)]
+pub struct MockUser {
+    pub username: String,
+    pub password: String,
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub roles: Vec<String>,
+}
+
+impl MockUser {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            email: format!("{}@example.com", username),
+            first_name: username.to_owned(),
+            last_name: "Test".to_owned(),
+            roles: vec![],
+        }
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.email = email.to_owned();
+        self
+    }
+
+    pub fn name(mut self, first_name: &str, last_name: &str) -> Self {
+        self.first_name = first_name.to_owned();
+        self.last_name = last_name.to_owned();
+        self
+    }
+
+    pub fn role(mut self, role: &str) -> Self {
+        self.roles.push(role.to_owned());
+        self
+    }
+}
+
+/// Failure the mock server answers with instead of handling a request.
+#[derive(Debug, Clone)]
+pub enum Failure {
+    /// `503` with an optional `Retry-After` in seconds.
+    Unavailable(Option<u64>),
+    /// `429` with an optional `Retry-After` in seconds.
+    RateLimited(Option<u64>),
+    /// `500` without body.
+    InternalError,
+    /// Any status with a raw body.
+    Status(StatusCode, String),
+}
+
+#[derive(Default)]
+struct MockState {
+    users: Vec<MockUser>,
+    service_account_roles: Vec<String>,
+    failures: VecDeque<Failure>,
+    always: Option<Failure>,
+    requests: Vec<(Method, String, HeaderMap)>,
+}
+
+struct Context {
+    url: String,
+    realm: String,
+    client_id: String,
+    client_secret: String,
+    keys: Arc<TestKeyPair>,
+    state: Mutex<MockState>,
+}
+
+/// Local keycloak server serving `/certs`, `/token`, `/token/introspect` and the admin
+/// `/users` endpoints of a single realm. Stopped when dropped.
+pub struct MockKeycloak {
+    context: Arc<Context>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockKeycloak {
+    pub const REALM: &'static str = "test";
+    pub const CLIENT_ID: &'static str = "test-client";
+    pub const CLIENT_SECRET: &'static str = "test-secret";
+
+    /// Starts the server on a random local port, signing with [`TestKeyPair::shared`].
+    pub async fn start() -> Self {
+        Self::start_with_keys(TestKeyPair::shared()).await
+    }
+
+    pub async fn start_with_keys(keys: Arc<TestKeyPair>) -> Self {
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)));
+        let addr = server.local_addr();
+        let context = Arc::new(Context {
+            url: format!("http://{}", addr),
+            realm: Self::REALM.to_owned(),
+            client_id: Self::CLIENT_ID.to_owned(),
+            client_secret: Self::CLIENT_SECRET.to_owned(),
+            keys,
+            state: Mutex::new(MockState::default()),
+        });
+
+        let service_context = context.clone();
+        let make_service = make_service_fn(move |_| {
+            let context = service_context.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let context = context.clone();
+                    async move { Ok::<_, Infallible>(context.handle(req).await) }
+                }))
+            }
+        });
+
+        let (shutdown, receiver) = oneshot::channel::<()>();
+        let server = server.serve(make_service).with_graceful_shutdown(async {
+            receiver.await.ok();
+        });
+        tokio::spawn(server);
+
+        Self {
+            context,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// Base url to hand to [`Keycloak::new`].
+    pub fn url(&self) -> &str {
+        &self.context.url
+    }
+
+    pub fn realm(&self) -> &str {
+        &self.context.realm
+    }
+
+    pub fn keys(&self) -> &TestKeyPair {
+        &self.context.keys
+    }
+
+    /// Client configured with this server's url, realm and client credentials.
+    pub fn client(&self) -> Keycloak {
+        Keycloak::new(
+            self.context.client_id.clone(),
+            self.context.client_secret.clone(),
+            self.context.realm.clone(),
+            self.context.url.clone(),
+        )
+    }
+
+    pub fn add_user(&self, user: MockUser) {
+        self.context.state().users.push(user);
+    }
+
+    pub fn user(&self, username: &str) -> Option<MockUser> {
+        self.context
+            .state()
+            .users
+            .iter()
+            .find(|user| user.username == username)
+            .cloned()
+    }
+
+    /// Realm roles put in tokens issued through the client credentials grant.
+    pub fn set_service_account_roles(&self, roles: &[&str]) {
+        self.context.state().service_account_roles =
+            roles.iter().map(|role| role.to_string()).collect();
+    }
+
+    /// Answers the next `times` requests with `failure`.
+    pub fn fail_next(&self, times: usize, failure: Failure) {
+        let mut state = self.context.state();
+        for _ in 0..times {
+            state.failures.push_back(failure.clone());
+        }
+    }
+
+    /// Answers every request with `failure` until [`MockKeycloak::reset_failures`].
+    pub fn fail_always(&self, failure: Failure) {
+        self.context.state().always = Some(failure);
+    }
+
+    pub fn reset_failures(&self) {
+        let mut state = self.context.state();
+        state.failures.clear();
+        state.always = None;
+    }
+
+    /// Number of requests received whose path ends with `suffix`, e.g. `"/certs"`.
+    pub fn request_count(&self, suffix: &str) -> usize {
+        self.context
+            .state()
+            .requests
+            .iter()
+            .filter(|(_, path, _)| path.ends_with(suffix))
+            .count()
+    }
+
+    /// Headers of the last request whose path ends with `suffix`.
+    pub fn last_request_headers(&self, suffix: &str) -> Option<HeaderMap> {
+        self.context
+            .state()
+            .requests
+            .iter()
+            .rev()
+            .find(|(_, path, _)| path.ends_with(suffix))
+            .map(|(_, _, headers)| headers.clone())
+    }
+}
+
+impl Drop for MockKeycloak {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NewUser {
+    username: String,
+    email: String,
+    #[serde(rename = "firstName", default)]
+    first_name: String,
+    #[serde(rename = "lastName", default)]
+    last_name: String,
+    #[serde(default)]
+    credentials: Vec<NewCredential>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewCredential {
+    value: String,
+}
+
+impl Context {
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let failure = {
+            let mut state = self.state();
+            state
+                .requests
+                .push((method.clone(), path.clone(), req.headers().clone()));
+            state.failures.pop_front().or_else(|| state.always.clone())
+        };
+        if let Some(failure) = failure {
+            return failure_response(failure);
+        }
+
+        let admin = format!("/admin/realms/{}", self.realm);
+        if path.starts_with(&admin) && !self.authorized(&req) {
+            return Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::empty())
+                .unwrap();
+        }
+
+        let oidc = format!("/realms/{}/protocol/openid-connect", self.realm);
+        match (method, path.as_str()) {
+            (Method::GET, p) if p == format!("{}/certs", oidc) => {
+                json_response(StatusCode::OK, self.keys.jwks())
+            }
+            (Method::POST, p) if p == format!("{}/token", oidc) => {
+                let form = read_form(req).await;
+                self.token(form)
+            }
+            (Method::POST, p) if p == format!("{}/token/introspect", oidc) => {
+                let form = read_form(req).await;
+                self.introspect(form)
+            }
+            (Method::GET, p) if p == format!("{}/users", admin) => self.list_users(),
+            (Method::POST, p) if p == format!("{}/users", admin) => {
+                let bytes = body::to_bytes(req.into_body()).await.unwrap_or_default();
+                match serde_json::from_slice::<NewUser>(&bytes) {
+                    Ok(user) => self.create_user(user),
+                    Err(err) => admin_error(StatusCode::BAD_REQUEST, &err.to_string()),
+                }
+            }
+            _ => json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "error": "HTTP 404 Not Found" }),
+            ),
+        }
+    }
+
+    fn token(&self, form: HashMap<String, String>) -> Response<Body> {
+        if !self.valid_client(&form) {
+            return oauth_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                "Invalid client or Invalid client credentials",
+            );
+        }
+        let grant_type = form.get("grant_type").map(String::as_str).unwrap_or("");
+        let subject = match grant_type {
+            "client_credentials" => None,
+            "password" => {
+                let username = form.get("username").cloned().unwrap_or_default();
+                let password = form.get("password").cloned().unwrap_or_default();
+                match self
+                    .state()
+                    .users
+                    .iter()
+                    .find(|user| user.username == username && user.password == password)
+                {
+                    Some(user) => Some(user.clone()),
+                    None => {
+                        return oauth_error(
+                            StatusCode::UNAUTHORIZED,
+                            "invalid_grant",
+                            "Invalid user credentials",
+                        )
+                    }
+                }
+            }
+            "refresh_token" => {
+                let claims = form
+                    .get("refresh_token")
+                    .and_then(|token| self.verify(token))
+                    .filter(|claims| claims["typ"] == "Refresh");
+                let claims = match claims {
+                    Some(claims) => claims,
+                    None => {
+                        return oauth_error(
+                            StatusCode::BAD_REQUEST,
+                            "invalid_grant",
+                            "Invalid refresh token",
+                        )
+                    }
+                };
+                let username = claims["preferred_username"].as_str().unwrap_or_default();
+                self.state()
+                    .users
+                    .iter()
+                    .find(|user| user.username == username)
+                    .cloned()
+            }
+            _ => {
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "unsupported_grant_type",
+                    "Unsupported grant_type",
+                )
+            }
+        };
+
+        let token = TestToken::new(self.keys.clone())
+            .issuer(&format!("{}/realms/{}", self.url, self.realm))
+            .azp(&self.client_id)
+            .expires_in(Duration::from_secs(ACCESS_TOKEN_LIFESPAN));
+        let token = match &subject {
+            Some(user) => token
+                .subject(&user.username)
+                .email(&user.email)
+                .name(&user.first_name, &user.last_name)
+                .roles(&user.roles.iter().map(String::as_str).collect::<Vec<_>>()),
+            None => {
+                let roles = self.state().service_account_roles.clone();
+                token
+                    .subject(&format!("service-account-{}", self.client_id))
+                    .claim("email", "")
+                    .name("", "")
+                    .roles(&roles.iter().map(String::as_str).collect::<Vec<_>>())
+            }
+        };
+        let refresh_token = token
+            .clone()
+            .claim("typ", "Refresh")
+            .expires_in(Duration::from_secs(REFRESH_TOKEN_LIFESPAN));
+
+        json_response(
+            StatusCode::OK,
+            json!({
+                "access_token": token.sign(),
+                "refresh_token": refresh_token.sign(),
+                "expires_in": ACCESS_TOKEN_LIFESPAN,
+                "refresh_expires_in": REFRESH_TOKEN_LIFESPAN,
+                "token_type": "Bearer",
+            }),
+        )
+    }
+
+    fn introspect(&self, form: HashMap<String, String>) -> Response<Body> {
+        if !self.valid_client(&form) {
+            return oauth_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                "Invalid client or Invalid client credentials",
+            );
+        }
+        match form.get("token").and_then(|token| self.verify(token)) {
+            Some(mut claims) => {
+                claims["active"] = json!(true);
+                json_response(StatusCode::OK, claims)
+            }
+            None => json_response(StatusCode::OK, json!({ "active": false })),
+        }
+    }
+
+    fn list_users(&self) -> Response<Body> {
+        let users = self
+            .state()
+            .users
+            .iter()
+            .map(|user| {
+                json!({
+                    "id": user.username,
+                    "username": user.username,
+                    "email": user.email,
+                    "firstName": user.first_name,
+                    "lastName": user.last_name,
+                    "enabled": true,
+                })
+            })
+            .collect::<Vec<_>>();
+        json_response(StatusCode::OK, json!(users))
+    }
+
+    fn create_user(&self, new_user: NewUser) -> Response<Body> {
+        let mut state = self.state();
+        if state
+            .users
+            .iter()
+            .any(|user| user.username == new_user.username)
+        {
+            return admin_error(StatusCode::CONFLICT, "User exists with same username");
+        }
+        if state.users.iter().any(|user| user.email == new_user.email) {
+            return admin_error(StatusCode::CONFLICT, "User exists with same email");
+        }
+        let location = format!(
+            "{}/admin/realms/{}/users/{}",
+            self.url, self.realm, new_user.username
+        );
+        state.users.push(MockUser {
+            password: new_user
+                .credentials
+                .into_iter()
+                .next()
+                .map(|credential| credential.value)
+                .unwrap_or_default(),
+            username: new_user.username,
+            email: new_user.email,
+            first_name: new_user.first_name,
+            last_name: new_user.last_name,
+            roles: vec![],
+        });
+        Response::builder()
+            .status(StatusCode::CREATED)
+            .header(LOCATION, location)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn valid_client(&self, form: &HashMap<String, String>) -> bool {
+        form.get("client_id") == Some(&self.client_id)
+            && form.get("client_secret") == Some(&self.client_secret)
+    }
+
+    fn authorized(&self, req: &Request<Body>) -> bool {
+        req.headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| self.verify(token))
+            .is_some()
+    }
+
+    /// Claims of a token signed by this server that has not expired.
+    fn verify(&self, token: &str) -> Option<Value> {
+        let key = self.keys.verifying_key().decoding_key().ok()?;
+        decode::<Value>(token, &key, &Validation::new(Algorithm::RS256))
+            .ok()
+            .map(|data| data.claims)
+    }
+}
+
+async fn read_form(req: Request<Body>) -> HashMap<String, String> {
+    let bytes = body::to_bytes(req.into_body()).await.unwrap_or_default();
+    serde_urlencoded::from_bytes(&bytes).unwrap_or_default()
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn oauth_error(status: StatusCode, error: &str, description: &str) -> Response<Body> {
+    json_response(
+        status,
+        json!({ "error": error, "error_description": description }),
+    )
+}
+
+fn admin_error(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "errorMessage": message }))
+}
+
+fn failure_response(failure: Failure) -> Response<Body> {
+    let (status, retry_after, body) = match failure {
+        Failure::Unavailable(retry_after) => {
+            (StatusCode::SERVICE_UNAVAILABLE, retry_after, String::new())
+        }
+        Failure::RateLimited(retry_after) => {
+            (StatusCode::TOO_MANY_REQUESTS, retry_after, String::new())
+        }
+        Failure::InternalError => (StatusCode::INTERNAL_SERVER_ERROR, None, String::new()),
+        Failure::Status(status, body) => (status, None, body),
+    };
+    let mut response = Response::builder().status(status);
+    if let Some(retry_after) = retry_after {
+        response = response.header(RETRY_AFTER, retry_after);
+    }
+    response.body(Body::from(body)).unwrap()
+}
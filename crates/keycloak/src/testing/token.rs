@@ -0,0 +1,226 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use super::keys::TestKeyPair;
+use crate::{error::KeycloakError, Keycloak};
+
+/// Mints signed tokens that a [`Keycloak`] instance accepts without a server.
+///
+/// ```ignore
+/// let minter = TokenMinter::new();
+/// let keycloak = minter.keycloak();
+/// let token = minter.token().subject("alice").roles(&["admin"]).sign();
+/// let claims = keycloak.decode::<TokenClaim>(token)?;
+/// ```
+#[derive(Clone)]
+pub struct TokenMinter {
+    keys: Arc<TestKeyPair>,
+}
+
+impl Default for TokenMinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenMinter {
+    /// Minter signing with [`TestKeyPair::shared`].
+    pub fn new() -> Self {
+        Self::with_keys(TestKeyPair::shared())
+    }
+
+    pub fn with_keys(keys: Arc<TestKeyPair>) -> Self {
+        Self { keys }
+    }
+
+    pub fn keys(&self) -> &Arc<TestKeyPair> {
+        &self.keys
+    }
+
+    /// Registers the public key in `keycloak` so `decode` accepts minted tokens.
+    pub fn register(&self, keycloak: &Keycloak) -> Result<(), KeycloakError> {
+        keycloak.add_key(self.keys.kid(), self.keys.verifying_key())
+    }
+
+    /// Offline client that only knows the minter's key; any call to the server fails.
+    pub fn keycloak(&self) -> Keycloak {
+        let keycloak = Keycloak::new(
+            "test-client".to_owned(),
+            "test-secret".to_owned(),
+            "test".to_owned(),
+            "http://127.0.0.1:0".to_owned(),
+        );
+        self.register(&keycloak)
+            .expect("failed to register test key");
+        keycloak
+    }
+
+    /// Token with the default claims of a user `test-user`, valid for five minutes.
+    pub fn token(&self) -> TestToken {
+        TestToken::new(self.keys.clone())
+    }
+}
+
+/// Claims of a token to mint, set through consuming setters and signed with [`TestToken::sign`].
+#[derive(Clone)]
+pub struct TestToken {
+    keys: Arc<TestKeyPair>,
+    kid: String,
+    claims: Map<String, Value>,
+}
+
+impl TestToken {
+    pub fn new(keys: Arc<TestKeyPair>) -> Self {
+        let now = now();
+        let claims = json!({
+            "iat": now,
+            "exp": now + 300,
+            "typ": "Bearer",
+            "azp": "test-client",
+            "sub": "test-user",
+            "preferred_username": "test-user",
+            "email": "test-user@example.com",
+            "given_name": "Test",
+            "family_name": "User",
+            "realm_access": { "roles": [] },
+        });
+        Self {
+            kid: keys.kid().to_owned(),
+            keys,
+            claims: match claims {
+                Value::Object(claims) => claims,
+                _ => Map::new(),
+            },
+        }
+    }
+
+    /// Sets `sub` and `preferred_username`.
+    pub fn subject(self, subject: &str) -> Self {
+        self.claim("sub", subject)
+            .claim("preferred_username", subject)
+    }
+
+    pub fn email(self, email: &str) -> Self {
+        self.claim("email", email)
+    }
+
+    pub fn name(self, given_name: &str, family_name: &str) -> Self {
+        self.claim("given_name", given_name)
+            .claim("family_name", family_name)
+    }
+
+    pub fn azp(self, azp: &str) -> Self {
+        self.claim("azp", azp)
+    }
+
+    pub fn issuer(self, issuer: &str) -> Self {
+        self.claim("iss", issuer)
+    }
+
+    pub fn audience(self, audience: &[&str]) -> Self {
+        match audience {
+            [single] => self.claim("aud", single),
+            _ => self.claim("aud", audience),
+        }
+    }
+
+    /// Realm roles, found in `realm_access.roles`.
+    pub fn roles(self, roles: &[&str]) -> Self {
+        self.claim("realm_access", json!({ "roles": roles }))
+    }
+
+    /// Client roles of `client`, found in `resource_access.<client>.roles`.
+    pub fn client_roles(mut self, client: &str, roles: &[&str]) -> Self {
+        let resource_access = self
+            .claims
+            .entry("resource_access")
+            .or_insert_with(|| json!({}));
+        resource_access[client] = json!({ "roles": roles });
+        self
+    }
+
+    /// Sets `exp` relative to now.
+    pub fn expires_in(self, expires_in: Duration) -> Self {
+        self.claim("exp", now() + expires_in.as_secs())
+    }
+
+    /// Sets `exp` well in the past, beyond the default validation leeway.
+    pub fn expired(self) -> Self {
+        self.claim("exp", now().saturating_sub(3600))
+    }
+
+    /// Signs with a different `kid` in the header, e.g. to test unknown keys.
+    pub fn kid(mut self, kid: &str) -> Self {
+        self.kid = kid.to_owned();
+        self
+    }
+
+    /// Sets or overrides any claim.
+    pub fn claim<T: Serialize>(mut self, name: &str, value: T) -> Self {
+        self.claims.insert(
+            name.to_owned(),
+            serde_json::to_value(value).expect("claim is not serializable"),
+        );
+        self
+    }
+
+    pub fn claims(&self) -> &Map<String, Value> {
+        &self.claims
+    }
+
+    pub fn sign(&self) -> String {
+        self.keys.sign_with_kid(&self.kid, &self.claims)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::TokenMinter;
+    use crate::{error::KeycloakError, TokenClaim};
+
+    #[test]
+    fn test_minted_tokens() -> Result<(), KeycloakError> {
+        let minter = TokenMinter::new();
+        let keycloak = minter.keycloak();
+
+        let token = minter
+            .token()
+            .subject("alice")
+            .email("alice@example.com")
+            .roles(&["admin", "user"])
+            .client_roles("billing", &["read"])
+            .audience(&["account"])
+            .expires_in(Duration::from_secs(60))
+            .sign();
+        let claims = keycloak.decode::<TokenClaim>(token)?;
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.email, "alice@example.com");
+        assert!(claims.has_role("admin"));
+        assert!(!claims.has_role("root"));
+        assert!(claims.has_client_role("billing", "read"));
+
+        let expired = minter.token().expired().sign();
+        assert!(matches!(
+            keycloak.decode::<TokenClaim>(expired),
+            Err(KeycloakError::JWTError(_))
+        ));
+
+        let unknown_kid = minter.token().kid("rotated-key").sign();
+        assert!(keycloak.decode::<TokenClaim>(unknown_kid).is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,45 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Access {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct TokenClaim {
     pub iat: u64,
     pub exp: u64,
     pub azp: String,
+    #[serde(default)]
+    pub sub: String,
+    #[serde(default)]
+    pub preferred_username: String,
+    #[serde(default)]
     pub given_name: String,
+    #[serde(default)]
     pub family_name: String,
+    #[serde(default)]
     pub email: String,
+    #[serde(default)]
+    pub realm_access: Access,
+    #[serde(default)]
+    pub resource_access: HashMap<String, Access>,
+}
+
+impl TokenClaim {
+    /// Whether the realm role `role` was granted.
+    pub fn has_role(&self, role: &str) -> bool {
+        self.realm_access.roles.iter().any(|r| r == role)
+    }
+
+    /// Whether the client role `role` of `client` was granted.
+    pub fn has_client_role(&self, client: &str, role: &str) -> bool {
+        self.resource_access
+            .get(client)
+            .map(|access| access.roles.iter().any(|r| r == role))
+            .unwrap_or(false)
+    }
 }
@@ -2,8 +2,9 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Default)]
 pub enum GrantType {
+    #[default]
     #[serde(rename = "password")]
     PASSWORD,
     #[serde(rename = "authorization_code")]
@@ -14,12 +15,6 @@ pub enum GrantType {
     RefreshToken,
 }
 
-impl Default for GrantType {
-    fn default() -> Self {
-        GrantType::PASSWORD
-    }
-}
-
 #[derive(Debug, Serialize, Default)]
 pub struct TokenRequest {
     pub username: Option<String>,